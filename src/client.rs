@@ -1,14 +1,49 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    fmt,
+    future::{pending, Future},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
-use futures_util::{
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::{
+    net::TcpStream,
+    sync::{oneshot, watch},
+    task::AbortHandle,
+    time::{interval_at, sleep, sleep_until, timeout, Instant},
+};
+use tokio_tungstenite::{
+    client_async_tls_with_config, connect_async_tls_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::client::Request as HandshakeRequest,
+        http::{
+            header::{IntoHeaderName, InvalidHeaderValue, AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL},
+            Extensions, HeaderMap, HeaderValue,
+        },
+        protocol::{frame::coding::CloseCode, CloseFrame as TCloseFrame, WebSocketConfig},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
 };
-use tokio::{net::TcpStream, sync::mpsc, time::sleep};
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 use tracing::{debug, error, info, instrument, warn};
 
-use crate::{request::Request, NextDoor};
+use crate::{
+    codec::BoxError,
+    connection::{receive_messages, send_messages, shutdown, Activity},
+    error::MissingExtension,
+    extract::{Connection, FromMesasge},
+    outbound::{Outbound, OverflowPolicy, PushError, QueueMetrics},
+    proxy::{Proxy, ProxyConfig, ProxyError},
+    reconnect::{ReconnectConfig, ReconnectPolicy},
+    request::{CloseFrame, ConnectionInfo, Request},
+    NextDoor,
+};
+
+pub use crate::connection::{Disconnect, ExecutionMode, KeyFn};
+pub use tokio_tungstenite::Connector;
 
 pub fn connect<S, T: Into<String>>(router: NextDoor<S>, url: T) -> Client<S>
 where
@@ -17,8 +52,21 @@ where
     Client {
         url: url.into(),
         router: Arc::new(router),
-        capacity: 100,
-        reconnect_config: None,
+        reconnect_policy: None,
+        outbound: Arc::new(Outbound::new(100, OverflowPolicy::Block)),
+        write_timeout: None,
+        hooks: Hooks::default(),
+        shutdown: Arc::new(watch::channel(false).0),
+        close_frame: CloseFrame {
+            reason: String::new(),
+            code: 1000,
+        },
+        close_timeout: Duration::from_secs(5),
+        keepalive: None,
+        connect_options: ConnectOptions::default(),
+        connect_target: None,
+        proxy: None,
+        execution_mode: ExecutionMode::Sequential,
     }
 }
 
@@ -28,211 +76,899 @@ pub enum ConnectError {
     WsError(#[from] tokio_tungstenite::tungstenite::Error),
     #[error("Max reconnection attempts reached")]
     MaxRetriesExceeded,
+    #[error("Failed to resolve connect target: {0}")]
+    Target(BoxError),
+    #[error(transparent)]
+    Proxy(#[from] ProxyError),
 }
 
-#[derive(Clone)]
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    #[error("Client is no longer running")]
+    Closed,
+    #[error("Outbound queue is full")]
+    Full,
+    #[error("Failed to serialize JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+impl From<PushError> for SendError {
+    fn from(err: PushError) -> Self {
+        match err {
+            PushError::Full => Self::Full,
+            PushError::Closed => Self::Closed,
+        }
+    }
+}
+
+/// Sends messages to the live connection of a [`Client`].
+///
+/// Messages share the outbound queue with handler replies. The queue outlives a single
+/// connection, so messages sent while reconnecting are written once the next connection is up.
+///
+/// When the queue is full, sending follows the client's [`OverflowPolicy`]. Ping and pong frames
+/// skip ahead of queued messages, close frames are written after them.
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    tx: Arc<Outbound>,
+}
+
+impl ClientHandle {
+    pub async fn send(&self, message: Message) -> Result<(), SendError> {
+        self.tx.send(message, false).await?;
+        Ok(())
+    }
+
+    /// Queue a message without waiting for room in the outbound queue.
+    pub fn try_send(&self, message: Message) -> Result<(), SendError> {
+        self.tx.try_send(message, false)?;
+        Ok(())
+    }
+
+    /// Queue a message ahead of the other queued messages, like a control frame.
+    ///
+    /// Priority messages are never dropped and do not count towards the capacity. They have a
+    /// small lane of their own, and this fails with [`SendError::Full`] while it is full.
+    pub fn send_priority(&self, message: Message) -> Result<(), SendError> {
+        self.tx.try_send(message, true)?;
+        Ok(())
+    }
+
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.tx.metrics()
+    }
+
+    pub async fn text<T: Into<String>>(&self, text: T) -> Result<(), SendError> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    pub async fn binary<B: Into<Vec<u8>>>(&self, data: B) -> Result<(), SendError> {
+        self.send(Message::Binary(data.into())).await
+    }
+
+    pub async fn json<T: Serialize>(&self, value: &T) -> Result<(), SendError> {
+        self.text(serde_json::to_string(value)?).await
+    }
+
+    /// Whether no connection is up: the client has not connected yet, is reconnecting or has
+    /// stopped.
+    ///
+    /// Messages sent while the client is reconnecting are still queued for the next connection.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed() || !self.tx.is_connected()
+    }
+}
+
+/// Handlers of a client connection can take the handle as an extractor.
+impl<S> FromMesasge<S> for ClientHandle {
+    type Rejection = MissingExtension;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        args.extensions()
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingExtension("ClientHandle"))
+    }
+}
+
+/// A WebSocket client that dispatches incoming messages to a [`NextDoor`] router.
+///
+/// A clone is a separate client with the same configuration. It gets its own outbound queue and
+/// shutdown signal, so [`ClientHandle`]s and [`ShutdownHandle`]s belong to the client they were
+/// taken from.
 pub struct Client<S> {
     url: String,
     router: Arc<NextDoor<S>>,
-    capacity: usize,
-    reconnect_config: Option<ReconnectConfig>,
+    reconnect_policy: Option<Arc<dyn ReconnectPolicy>>,
+    outbound: Arc<Outbound>,
+    write_timeout: Option<Duration>,
+    hooks: Hooks,
+    shutdown: Arc<watch::Sender<bool>>,
+    close_frame: CloseFrame,
+    close_timeout: Duration,
+    keepalive: Option<KeepaliveConfig>,
+    connect_options: ConnectOptions,
+    connect_target: Option<ConnectTargetProvider<S>>,
+    proxy: Option<ProxyConfig>,
+    execution_mode: ExecutionMode,
+}
+
+impl<S> Clone for Client<S> {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            router: self.router.clone(),
+            reconnect_policy: self.reconnect_policy.clone(),
+            outbound: Arc::new(self.outbound.empty_like()),
+            write_timeout: self.write_timeout,
+            hooks: self.hooks.clone(),
+            shutdown: Arc::new(watch::channel(false).0),
+            close_frame: self.close_frame.clone(),
+            close_timeout: self.close_timeout,
+            keepalive: self.keepalive.clone(),
+            connect_options: self.connect_options.clone(),
+            connect_target: self.connect_target.clone(),
+            proxy: self.proxy.clone(),
+            execution_mode: self.execution_mode.clone(),
+        }
+    }
+}
+
+/// Stops a running [`Client`] from another task.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
 }
 
 impl<S> Client<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    #[instrument(skip(self), fields(url = %self.url))]
+    /// Run until ctrl-c.
     pub async fn run(self) -> Result<(), ConnectError> {
-        let mut current_url = self.url;
-        let mut retry_count = 0;
-        let mut delay = self
-            .reconnect_config
-            .as_ref()
-            .map_or(0, |config| config.initial_delay);
+        self.run_with_shutdown(shutdown()).await
+    }
+
+    /// Run until `signal` completes or [`ShutdownHandle::shutdown`] is called.
+    ///
+    /// On shutdown the queued outbound messages are flushed, the close frame is sent and the
+    /// client waits up to the close timeout for the peer to finish the close handshake.
+    #[instrument(skip(self, signal), fields(url = %self.url))]
+    pub async fn run_with_shutdown<F>(self, signal: F) -> Result<(), ConnectError>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        // Handles fail with `SendError::Closed` once the client stops.
+        let _close_queue = CloseOnDrop(self.outbound.clone());
+        let mut shutdown_rx = self.shutdown.subscribe();
+        let signal = async move {
+            tokio::select! {
+                _ = signal => {}
+                _ = shutdown_rx.wait_for(|shutdown| *shutdown) => {}
+            }
+        };
+        tokio::pin!(signal);
+
+        let mut current_url = self.url.clone();
+        let mut attempt = 0;
+        let mut connected_once = false;
 
         loop {
             debug!("Establishing WebSocket connection");
-            match connect_async(&current_url).await {
-                Ok((ws_stream, response)) => {
-                    let (write, read) = ws_stream.split();
-                    debug!(status = ?response.status(), "WebSocket connection established");
-                    let (tx, rx) = mpsc::channel(self.capacity);
-
-                    let router = self.router.clone();
-                    let recv_task = tokio::spawn(receive_messages(read, router, tx));
-                    let send_task = tokio::spawn(send_messages(write, rx));
-
-                    tokio::select! {
-                        result = recv_task => {
-                            match result {
-                                Ok((should_reconnect,maybe_new_url)) => {
-                                    if should_reconnect {
-                                        if let Some(new_url) = maybe_new_url {
-                                            info!("Initiating reconnection to new URL: {}", new_url);
-                                            current_url = new_url;
-                                        } else {
-                                            info!("Initiating reconnection to same URL");
-                                        }
-
-                                        sleep(Duration::from_secs(1)).await;
-                                        continue;
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(error = %e, "Receive task join error");
-                                    println!( "Receive task join error");
-                                }
-                            }
-                       }
-                        _ = send_task => {}
-                        _ = shutdown() => {
-                            info!("Shutting down gracefully");
-                            break;
+            let connecting = tokio::select! {
+                result = self.handshake(&current_url) => result,
+                _ = &mut signal => break,
+            };
+
+            let delay = match connecting {
+                Ok((ws_stream, response, url)) => {
+                    connected_once = true;
+                    let connected_at = Instant::now();
+                    let disconnect = self
+                        .run_connection(ws_stream, response, &url, signal.as_mut())
+                        .await;
+
+                    let stable_after = match &self.reconnect_policy {
+                        Some(policy) => policy.stable_after(),
+                        None => ReconnectConfig::default().stable_after(),
+                    };
+                    if connected_at.elapsed() >= stable_after {
+                        attempt = 0;
+                    }
+
+                    match &disconnect {
+                        Disconnect::Reconnect(Some(new_url)) => {
+                            info!("Initiating reconnection to new URL: {}", new_url);
+                            current_url = new_url.clone();
                         }
+                        Disconnect::Reconnect(None) => {
+                            info!("Initiating reconnection to same URL");
+                        }
+                        _ => {}
+                    }
+                    let shutting_down = matches!(disconnect, Disconnect::Shutdown);
+
+                    debug!(?disconnect, "WebSocket connection closed");
+                    if let Some(hook) = &self.hooks.on_disconnect {
+                        hook(disconnect, self.handle()).await;
+                    }
+
+                    if shutting_down {
+                        break;
+                    }
+
+                    attempt += 1;
+                    match &self.reconnect_policy {
+                        Some(policy) => match policy.next_delay(attempt) {
+                            Some(delay) => delay,
+                            None => {
+                                error!(attempt, "Reconnect policy gave up after disconnect");
+                                return Err(ConnectError::MaxRetriesExceeded);
+                            }
+                        },
+                        None => ReconnectConfig::default().backoff(attempt),
                     }
                 }
                 Err(e) => {
-                    let Some(reconnect_config) = &self.reconnect_config else {
-                        return Err(ConnectError::WsError(e));
+                    attempt += 1;
+                    let delay = match &self.reconnect_policy {
+                        Some(policy) => policy.next_delay(attempt),
+                        None if connected_once => Some(ReconnectConfig::default().backoff(attempt)),
+                        None => return Err(e),
                     };
-
-                    if retry_count >= reconnect_config.max_retries {
-                        error!(
-                            error = %e,
-                            max_retries = reconnect_config.max_retries,
-                            "Max reconnection attempts reached"
-                        );
+                    let Some(delay) = delay else {
+                        error!(error = %e, attempt, "Max reconnection attempts reached");
                         return Err(ConnectError::MaxRetriesExceeded);
-                    }
-
-                    retry_count += 1;
-                    delay = calculate_next_delay(
-                        delay,
-                        reconnect_config.backoff_factor,
-                        reconnect_config.max_delay,
-                    );
+                    };
 
                     warn!(
                         error = %e,
-                        retry_count,
-                        next_attempt_delay_ms = delay,
-                        max_retries = reconnect_config.max_retries,
+                        attempt,
+                        next_attempt_delay_ms = delay.as_millis() as u64,
                         "Connection failed, attempting to reconnect"
                     );
-
-                    sleep(Duration::from_millis(delay)).await;
-                    continue;
+                    delay
                 }
+            };
+
+            self.reconnecting(attempt, delay, &current_url).await;
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = &mut signal => break,
             }
         }
 
+        info!("Shutting down gracefully");
         Ok(())
     }
 
+    /// Resolve the connect target and perform the handshake, returning the URL connected to.
+    async fn handshake(
+        &self,
+        url: &str,
+    ) -> Result<
+        (
+            WebSocketStream<MaybeTlsStream<TcpStream>>,
+            HandshakeResponse,
+            String,
+        ),
+        ConnectError,
+    > {
+        let target = match &self.connect_target {
+            Some(provider) => provider(url.to_string(), self.router.state.clone())
+                .await
+                .map_err(ConnectError::Target)?,
+            None => ConnectTarget::new(url),
+        };
+
+        let options = &self.connect_options;
+        let mut request = options.request(&target.url)?;
+        let headers = request.headers_mut();
+        for key in target.headers.keys() {
+            headers.remove(key);
+        }
+        for (key, value) in &target.headers {
+            headers.append(key, value.clone());
+        }
+
+        let uri = request.uri();
+        let scheme = uri.scheme_str().unwrap_or("ws");
+        let host = uri.host().unwrap_or_default();
+        let port = uri
+            .port_u16()
+            .unwrap_or(if scheme == "wss" { 443 } else { 80 });
+        let proxy = self
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.resolve(scheme, host, port));
+
+        let (ws_stream, response) = match proxy {
+            Some(proxy) => {
+                debug!(?proxy, "Tunneling through proxy");
+                let stream = proxy.tunnel(host, port).await?;
+                client_async_tls_with_config(
+                    request,
+                    stream,
+                    options.config,
+                    options.connector.clone(),
+                )
+                .await?
+            }
+            None => {
+                connect_async_tls_with_config(
+                    request,
+                    options.config,
+                    false,
+                    options.connector.clone(),
+                )
+                .await?
+            }
+        };
+        Ok((ws_stream, response, target.url))
+    }
+
+    async fn run_connection(
+        &self,
+        ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+        response: HandshakeResponse,
+        url: &str,
+        signal: Pin<&mut impl Future<Output = ()>>,
+    ) -> Disconnect {
+        let (write, read) = ws_stream.split();
+        self.outbound.reset_overflow();
+        self.outbound.set_connected(true);
+        let _connected = DisconnectOnDrop(self.outbound.clone());
+        debug!(status = ?response.status(), "WebSocket connection established");
+
+        let (close_tx, close_rx) = oneshot::channel();
+        let close = async move { close_rx.await.unwrap_or(Message::Close(None)) };
+
+        let mut info = ConnectionInfo::new(url.to_string(), response.headers().clone());
+        info.protocol = response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocol| protocol.to_str().ok())
+            .map(String::from);
+        let mut extensions = Extensions::new();
+        extensions.insert(Connection(Arc::new(info)));
+        extensions.insert(self.handle());
+
+        let activity = Activity::new();
+        let router = self.router.clone();
+        let mut recv_task = tokio::spawn(receive_messages(
+            read,
+            router,
+            self.outbound.clone(),
+            activity.clone(),
+            extensions,
+            self.execution_mode.clone(),
+        ));
+        let queue = self.outbound.clone();
+        let write_timeout = self.write_timeout;
+        let mut send_task =
+            tokio::spawn(async move { send_messages(write, &queue, close, write_timeout).await });
+
+        // The outbound queue outlives the connection, so the writer only stops
+        // on a write error; abort whichever half is still running.
+        let _abort_recv = AbortOnDrop(recv_task.abort_handle());
+        let _abort_send = AbortOnDrop(send_task.abort_handle());
+
+        if let Some(hook) = &self.hooks.on_connect {
+            hook(response, self.handle()).await;
+        }
+
+        tokio::select! {
+            result = &mut recv_task => {
+                result.unwrap_or_else(|e| {
+                    error!(error = %e, "Receive task join error");
+                    Disconnect::Closed(None)
+                })
+            }
+            result = &mut send_task => {
+                match result {
+                    Ok(Err(e)) => Disconnect::Error(e),
+                    Ok(Ok(())) => Disconnect::Closed(None),
+                    Err(e) => {
+                        error!(error = %e, "Send task join error");
+                        Disconnect::Closed(None)
+                    }
+                }
+            }
+            disconnect = self.keepalive(activity) => disconnect,
+            _ = self.outbound.overflowed() => {
+                warn!(
+                    capacity = self.outbound.metrics().capacity,
+                    "Outbound queue overflowed, dropping connection"
+                );
+                Disconnect::Overflow
+            }
+            _ = signal => {
+                debug!("Flushing outbound queue and closing connection");
+                let frame = TCloseFrame {
+                    code: CloseCode::from(self.close_frame.code),
+                    reason: self.close_frame.reason.clone().into(),
+                };
+                let _ = close_tx.send(Message::Close(Some(frame)));
+
+                let closed = async {
+                    let _ = (&mut send_task).await;
+                    let _ = (&mut recv_task).await;
+                };
+                if timeout(self.close_timeout, closed).await.is_err() {
+                    warn!(
+                        timeout_ms = self.close_timeout.as_millis() as u64,
+                        "Timed out waiting for the peer to close the connection"
+                    );
+                }
+                Disconnect::Shutdown
+            }
+        }
+    }
+
+    /// Send the keepalive message every interval and return once the connection has been idle
+    /// for longer than the timeout. Never returns without a keepalive config.
+    async fn keepalive(&self, activity: Activity) -> Disconnect {
+        let Some(config) = &self.keepalive else {
+            return pending().await;
+        };
+
+        let idle = async {
+            let Some(timeout) = config.timeout else {
+                return pending().await;
+            };
+            loop {
+                let deadline = activity.last() + timeout;
+                if deadline <= Instant::now() {
+                    return timeout;
+                }
+                sleep_until(deadline).await;
+            }
+        };
+        tokio::pin!(idle);
+
+        let mut ticks = interval_at(Instant::now() + config.interval, config.interval);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {
+                    debug!("Sending keepalive");
+                    if self.outbound.send(config.message.clone(), true).await.is_err() {
+                        return pending().await;
+                    }
+                }
+                timeout = &mut idle => {
+                    warn!(
+                        timeout_ms = timeout.as_millis() as u64,
+                        "No frames received within keepalive timeout"
+                    );
+                    return Disconnect::Timeout;
+                }
+            }
+        }
+    }
+
+    async fn reconnecting(&self, attempt: u32, delay: Duration, url: &str) {
+        if let Some(hook) = &self.hooks.on_reconnect {
+            let attempt = ReconnectAttempt {
+                attempt,
+                delay,
+                url: url.to_string(),
+            };
+            hook(attempt, self.handle()).await;
+        }
+    }
+
+    /// Set the outbound queue capacity, 100 by default.
+    ///
+    /// The queue is resized in place, so handles taken earlier keep working. Messages already
+    /// queued past a smaller capacity are still sent.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.outbound.set_capacity(capacity);
+        self
+    }
+
+    /// Like [`with_capacity`](Self::with_capacity), for a client behind a reference.
     pub fn set_capacity(&mut self, capacity: usize) -> &mut Self {
-        self.capacity = capacity;
+        self.outbound.set_capacity(capacity);
+        self
+    }
+
+    /// What happens to messages sent while the outbound queue is full,
+    /// [`OverflowPolicy::Block`] by default.
+    pub fn with_overflow_policy(self, policy: OverflowPolicy) -> Self {
+        self.outbound.set_policy(policy);
+        self
+    }
+
+    /// Drop the connection when writing a single message takes longer than `timeout`.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Depth and counters of the outbound queue.
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.outbound.metrics()
+    }
+
+    /// A cloneable handle for sending messages from outside of handlers.
+    pub fn handle(&self) -> ClientHandle {
+        ClientHandle {
+            tx: self.outbound.clone(),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            tx: self.shutdown.clone(),
+        }
+    }
+
+    /// The close frame sent on shutdown, `1000` with an empty reason by default.
+    pub fn with_close_frame(mut self, frame: CloseFrame) -> Self {
+        self.close_frame = frame;
+        self
+    }
+
+    /// How long to wait for the peer to finish the close handshake on shutdown, 5 seconds by default.
+    pub fn with_close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
+
+    /// Send a keepalive message on an interval and reconnect when the peer goes quiet.
+    pub fn with_keepalive(mut self, config: KeepaliveConfig) -> Self {
+        self.keepalive = Some(config);
+        self
+    }
+
+    /// Headers, subprotocols, TLS and limits of the handshake, used for every connect attempt.
+    pub fn with_connect_options(mut self, options: ConnectOptions) -> Self {
+        self.connect_options = options;
+        self
+    }
 
+    /// Resolve the URL and headers before every connect attempt, such as to fetch a fresh token.
+    ///
+    /// `provider` gets the URL the client would connect to, the configured one or the one from a
+    /// [`Status::Reconnect`](crate::response::Status::Reconnect) response, and the router state.
+    /// Its headers replace the [`ConnectOptions`] headers of the same name. An error counts as a
+    /// failed attempt.
+    ///
+    /// ```ignore
+    /// client.with_connect_target(|url, state: Arc<AppState>| async move {
+    ///     let token = state.auth.refresh().await?;
+    ///     Ok(ConnectTarget::new(format!("{}?token={}", url, token)))
+    /// });
+    /// ```
+    pub fn with_connect_target<F, Fut>(mut self, provider: F) -> Self
+    where
+        F: Fn(String, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<ConnectTarget, BoxError>> + Send + 'static,
+    {
+        self.connect_target = Some(Arc::new(move |url, state| Box::pin(provider(url, state))));
         self
     }
 
-    pub fn with_reconnect_config(mut self, config: ReconnectConfig) -> Self {
-        self.reconnect_config = Some(config);
+    /// How handlers of incoming messages run, [`ExecutionMode::Sequential`] by default.
+    ///
+    /// ```ignore
+    /// client.with_execution_mode(ExecutionMode::json_field(256, "channel"));
+    /// ```
+    pub fn with_execution_mode(mut self, mode: ExecutionMode) -> Self {
+        self.execution_mode = mode;
+        self
+    }
+
+    /// Tunnel every connection through `proxy`, except hosts matching its no-proxy list.
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(ProxyConfig::Fixed(proxy));
+        self
+    }
+
+    /// Use the proxy from `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`, read before
+    /// every connect attempt. See [`Proxy::from_env`].
+    pub fn with_env_proxy(mut self) -> Self {
+        self.proxy = Some(ProxyConfig::Env);
+        self
+    }
+
+    pub fn with_reconnect_config(self, config: ReconnectConfig) -> Self {
+        self.with_reconnect_policy(config)
+    }
+
+    /// Decide when to reconnect after a failed attempt or a dropped connection.
+    ///
+    /// Without a policy a failed first connect returns the error. Once a connection has been
+    /// up, dropped connections and failed reconnects are retried with the backoff of the default
+    /// [`ReconnectConfig`], without a retry limit.
+    pub fn with_reconnect_policy<P>(mut self, policy: P) -> Self
+    where
+        P: ReconnectPolicy,
+    {
+        self.reconnect_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Called after every successful handshake, including reconnects.
+    ///
+    /// ```ignore
+    /// client.on_connect(|_, handle| async move {
+    ///     handle.json(&Subscribe { channel: "trades" }).await.ok();
+    /// });
+    /// ```
+    pub fn on_connect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(HandshakeResponse, ClientHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_connect = Some(Arc::new(move |response, handle| {
+            Box::pin(hook(response, handle))
+        }));
+        self
+    }
+
+    /// Called when an established connection ends.
+    ///
+    /// Messages sent through the handle are queued for the next connection.
+    pub fn on_disconnect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Disconnect, ClientHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_disconnect = Some(Arc::new(move |disconnect, handle| {
+            Box::pin(hook(disconnect, handle))
+        }));
+        self
+    }
+
+    /// Called before waiting to reconnect, after a dropped connection or a failed attempt.
+    pub fn on_reconnect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(ReconnectAttempt, ClientHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_reconnect = Some(Arc::new(move |attempt, handle| {
+            Box::pin(hook(attempt, handle))
+        }));
         self
     }
 }
 
+/// HTTP response of the WebSocket handshake.
+pub type HandshakeResponse = tokio_tungstenite::tungstenite::handshake::client::Response;
+
+/// Passed to [`Client::on_reconnect`] before the client waits to reconnect.
+#[derive(Debug, Clone)]
+pub struct ReconnectAttempt {
+    pub attempt: u32,
+    pub delay: Duration,
+    pub url: String,
+}
+
+/// Where a [`Client`] connects, produced by [`Client::with_connect_target`].
 #[derive(Clone)]
-pub struct ReconnectConfig {
-    pub initial_delay: u64,
-    pub max_delay: u64,
-    pub max_retries: u32,
-    pub backoff_factor: f64,
+pub struct ConnectTarget {
+    pub url: String,
+    pub headers: HeaderMap,
 }
 
-impl Default for ReconnectConfig {
+impl ConnectTarget {
+    pub fn new<T: Into<String>>(url: T) -> Self {
+        Self {
+            url: url.into(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Add a header to the handshake request, like [`ConnectOptions::header`].
+    pub fn header<K, V>(mut self, key: K, value: V) -> Result<Self, InvalidHeaderValue>
+    where
+        K: IntoHeaderName,
+        V: TryInto<HeaderValue, Error = InvalidHeaderValue>,
+    {
+        self.headers.append(key, value.try_into()?);
+        Ok(self)
+    }
+}
+
+impl fmt::Debug for ConnectTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Header values may carry credentials.
+        f.debug_struct("ConnectTarget")
+            .field("url", &self.url)
+            .field("headers", &self.headers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+type ConnectTargetProvider<S> = Arc<
+    dyn Fn(String, S) -> Pin<Box<dyn Future<Output = Result<ConnectTarget, BoxError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+type Hook<T> =
+    Arc<dyn Fn(T, ClientHandle) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct Hooks {
+    on_connect: Option<Hook<HandshakeResponse>>,
+    on_disconnect: Option<Hook<Disconnect>>,
+    on_reconnect: Option<Hook<ReconnectAttempt>>,
+}
+
+/// Keepalive of a [`Client`] connection.
+///
+/// `message` is sent every `interval`. Any frame from the peer counts as activity, and when
+/// nothing arrives for `timeout` the connection is dropped and the client reconnects.
+///
+/// ```ignore
+/// client.with_keepalive(KeepaliveConfig {
+///     message: Message::Text(r#"{"op":"ping"}"#.to_string()),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeepaliveConfig {
+    pub interval: Duration,
+    pub message: Message,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for KeepaliveConfig {
     fn default() -> Self {
         Self {
-            initial_delay: 1000,
-            max_delay: 30000,
-            max_retries: 8,
-            backoff_factor: 1.5,
+            interval: Duration::from_secs(30),
+            message: Message::Ping(Vec::new()),
+            timeout: Some(Duration::from_secs(90)),
         }
     }
 }
-fn calculate_next_delay(current_delay: u64, backoff_factor: f64, max_delay: u64) -> u64 {
-    ((current_delay as f64 * backoff_factor) as u64).min(max_delay)
+
+/// Handshake request and transport settings of a [`Client`].
+///
+/// ```ignore
+/// client.with_connect_options(
+///     ConnectOptions::new()
+///         .bearer_token(&token)?
+///         .protocols(["feed.v2"])
+///         .max_message_size(Some(16 << 20)),
+/// );
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectOptions {
+    headers: HeaderMap,
+    protocols: Vec<String>,
+    connector: Option<Connector>,
+    config: Option<WebSocketConfig>,
 }
 
-async fn handle_message<S>(
-    msg: Message,
-    router: Arc<NextDoor<S>>,
-    tx: &mpsc::Sender<Message>,
-) -> Option<(bool, Option<String>)>
-where
-    S: Clone + Send + Sync + 'static,
-{
-    debug!(?msg, "Received WebSocket message");
-    let request = Request::from_ws_message(msg);
-    let response = router.handler(request).await;
-    debug!(status = ?response.status, "Sending successful response");
+impl ConnectOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a header to the handshake request, such as a cookie or the `Origin`.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Result<Self, InvalidHeaderValue>
+    where
+        K: IntoHeaderName,
+        V: TryInto<HeaderValue, Error = InvalidHeaderValue>,
+    {
+        self.headers.append(key, value.try_into()?);
+        Ok(self)
+    }
 
-    if response.status.is_reconnect() {
-        return Some((true, Some(response.body)));
+    /// Send `Authorization: Bearer <token>` with the handshake request.
+    pub fn bearer_token(mut self, token: &str) -> Result<Self, InvalidHeaderValue> {
+        let mut value = HeaderValue::try_from(format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        self.headers.append(AUTHORIZATION, value);
+        Ok(self)
+    }
+
+    /// Subprotocols offered in `Sec-WebSocket-Protocol`, in order of preference.
+    ///
+    /// The server must accept one of them. Handlers see the accepted subprotocol in
+    /// [`ConnectionInfo::protocol`].
+    pub fn protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
     }
 
-    if response.status.is_success() {
-        if tx.send(Message::Text(response.body)).await.is_err() {
-            return Some((false, None));
+    /// TLS connector for `wss://` URLs, for example a rustls `ClientConfig` with private roots,
+    /// client certificates or ALPN protocols. The webpki roots are used by default.
+    pub fn connector(mut self, connector: Connector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    pub fn config(mut self, config: WebSocketConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Largest message accepted from the peer, `None` for no limit.
+    pub fn max_message_size(mut self, size: Option<usize>) -> Self {
+        self.config
+            .get_or_insert_with(Default::default)
+            .max_message_size = size;
+        self
+    }
+
+    /// Largest frame accepted from the peer, `None` for no limit.
+    pub fn max_frame_size(mut self, size: Option<usize>) -> Self {
+        self.config
+            .get_or_insert_with(Default::default)
+            .max_frame_size = size;
+        self
+    }
+
+    // Same error type as the handshake it feeds.
+    #[allow(clippy::result_large_err)]
+    fn request(
+        &self,
+        url: &str,
+    ) -> Result<HandshakeRequest, tokio_tungstenite::tungstenite::Error> {
+        let mut request = url.into_client_request()?;
+        let headers = request.headers_mut();
+        for (key, value) in &self.headers {
+            headers.append(key, value.clone());
+        }
+        if !self.protocols.is_empty() {
+            // tungstenite does not trim the offered subprotocols when it checks the
+            // server's choice, so no space after the comma.
+            let protocols = HeaderValue::try_from(self.protocols.join(","))?;
+            headers.insert(SEC_WEBSOCKET_PROTOCOL, protocols);
         }
-    } else {
-        warn!(
-            status = ?response.status,
-            body = %response.body,
-            "Handler returned error response"
-        );
+        Ok(request)
     }
-    None
 }
 
-async fn receive_messages<S>(
-    mut read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    router: Arc<NextDoor<S>>,
-    tx: mpsc::Sender<Message>,
-) -> (bool, Option<String>)
-where
-    S: Clone + Send + Sync + 'static,
-{
-    while let Some(msg) = read.next().await {
-        match msg {
-            Ok(msg) => {
-                if let Some(result) = handle_message(msg, router.clone(), &tx).await {
-                    return result;
-                }
-            }
-            Err(e) => {
-                error!(error = %e, "Error receiving WebSocket message");
-                return (false, None);
-            }
-        }
+impl fmt::Debug for ConnectOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Header values may carry credentials.
+        f.debug_struct("ConnectOptions")
+            .field("headers", &self.headers.keys().collect::<Vec<_>>())
+            .field("protocols", &self.protocols)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
     }
-    (false, None)
 }
 
-async fn send_messages(
-    mut write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    mut rx: mpsc::Receiver<Message>,
-) {
-    while let Some(msg) = rx.recv().await {
-        if let Err(e) = write.send(msg).await {
-            error!(error = %e, "Error sending WebSocket message");
-            break;
-        }
+struct CloseOnDrop(Arc<Outbound>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
     }
 }
 
-async fn shutdown() {
-    if let Err(e) = tokio::signal::ctrl_c().await {
-        error!(error = %e, "Failed to listen for ctrl-c signal");
+/// Marks the outbound queue as having no connection once the connection ends.
+struct DisconnectOnDrop(Arc<Outbound>);
+
+impl Drop for DisconnectOnDrop {
+    fn drop(&mut self) {
+        self.0.set_connected(false);
+    }
+}
+
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
     }
 }
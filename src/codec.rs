@@ -0,0 +1,264 @@
+use std::error::Error;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::DecodeRejection,
+    extract::FromMesasge,
+    request::{Frames, Request},
+    response::{IntoResponse, Response, Status},
+};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// A serde data format for message bodies.
+pub trait Codec {
+    /// Name of the format, used in rejections.
+    const NAME: &'static str;
+    /// The frame encoded values are sent as.
+    const FRAME: Frames;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError>;
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, BoxError>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    const NAME: &'static str = "JSON";
+    const FRAME: Frames = Frames::Text;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, BoxError> {
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+/// MessagePack with struct fields encoded as maps.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy)]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    const NAME: &'static str = "MessagePack";
+    const FRAME: Frames = Frames::Binary;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, BoxError> {
+        Ok(rmp_serde::from_slice(body)?)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    const NAME: &'static str = "CBOR";
+    const FRAME: Frames = Frames::Binary;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        let mut body = Vec::new();
+        ciborium::into_writer(value, &mut body)?;
+        Ok(body)
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, BoxError> {
+        Ok(ciborium::from_reader(body)?)
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    const NAME: &'static str = "bincode";
+    const FRAME: Frames = Frames::Binary;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, BoxError> {
+        Ok(bincode::deserialize(body)?)
+    }
+}
+
+fn decode<C: Codec, T: DeserializeOwned>(args: &Request) -> Result<T, DecodeRejection> {
+    C::decode(&args.body()).map_err(|source| DecodeRejection {
+        codec: C::NAME,
+        source,
+    })
+}
+
+fn encode<C: Codec, T: Serialize>(value: &T) -> Response {
+    match C::encode(value) {
+        Ok(body) => Response::with_frame(Status::OK, C::FRAME, body),
+        Err(err) => Response::error(
+            Status::InternalError,
+            format!("Failed to encode {}: {}", C::NAME, err),
+        ),
+    }
+}
+
+macro_rules! impl_codec {
+    ($(#[$meta:meta])* $name:ident, $codec:ty) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone)]
+        pub struct $name<T>(pub T);
+
+        $(#[$meta])*
+        impl<T, S> FromMesasge<S> for $name<T>
+        where
+            T: DeserializeOwned,
+        {
+            type Rejection = DecodeRejection;
+            fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+                decode::<$codec, T>(args).map(Self)
+            }
+        }
+
+        $(#[$meta])*
+        impl<T> IntoResponse for $name<T>
+        where
+            T: Serialize,
+        {
+            fn into_response(self) -> Response {
+                encode::<$codec, T>(&self.0)
+            }
+        }
+    };
+}
+
+impl_codec!(
+    /// MessagePack body, sent as a binary frame.
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    MsgPackCodec
+);
+impl_codec!(
+    /// CBOR body, sent as a binary frame.
+    #[cfg(feature = "cbor")]
+    Cbor,
+    CborCodec
+);
+impl_codec!(
+    /// bincode body, sent as a binary frame.
+    #[cfg(feature = "bincode")]
+    Bincode,
+    BincodeCodec
+);
+
+/// Protocol Buffers body, sent as a binary frame.
+#[cfg(feature = "protobuf")]
+#[derive(Debug, Clone)]
+pub struct Protobuf<T>(pub T);
+
+#[cfg(feature = "protobuf")]
+impl<T, S> FromMesasge<S> for Protobuf<T>
+where
+    T: prost::Message + Default,
+{
+    type Rejection = DecodeRejection;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        T::decode(args.body())
+            .map(Self)
+            .map_err(|err| DecodeRejection {
+                codec: "Protocol Buffers",
+                source: Box::new(err),
+            })
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T> IntoResponse for Protobuf<T>
+where
+    T: prost::Message,
+{
+    fn into_response(self) -> Response {
+        Response::binary(self.0.encode_to_vec())
+    }
+}
+
+/// The format [`Typed`] bodies use, set per router with
+/// [`NextDoor::codec`](crate::NextDoor::codec).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl Format {
+    /// The format of the router that dispatched `args`.
+    pub fn of(args: &Request) -> Self {
+        args.extensions().get::<Self>().copied().unwrap_or_default()
+    }
+
+    fn decode<T: DeserializeOwned>(self, args: &Request) -> Result<T, DecodeRejection> {
+        match self {
+            Self::Json => decode::<JsonCodec, T>(args),
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => decode::<MsgPackCodec, T>(args),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => decode::<CborCodec, T>(args),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => decode::<BincodeCodec, T>(args),
+        }
+    }
+
+    fn encode<T: Serialize>(self, value: &T) -> Response {
+        match self {
+            Self::Json => encode::<JsonCodec, T>(value),
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => encode::<MsgPackCodec, T>(value),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => encode::<CborCodec, T>(value),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => encode::<BincodeCodec, T>(value),
+        }
+    }
+}
+
+/// Body in the router's default [`Format`].
+///
+/// As a response it is encoded once the handler returns, in the format of the router
+/// that dispatched the request.
+#[derive(Debug, Clone)]
+pub struct Typed<T>(pub T);
+
+impl<T, S> FromMesasge<S> for Typed<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = DecodeRejection;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        Format::of(args).decode(args).map(Self)
+    }
+}
+
+impl<T> IntoResponse for Typed<T>
+where
+    T: Serialize + Send + 'static,
+{
+    fn into_response(self) -> Response {
+        Response::deferred(move |format| format.encode(&self.0))
+    }
+}
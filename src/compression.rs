@@ -0,0 +1,224 @@
+//! Application level compression of message bodies.
+//!
+//! tungstenite does not implement the permessage-deflate extension, so compressed frames are
+//! handled here: [`Decompressed`] inflates bodies before an inner extractor runs, and
+//! [`Compressed`] or [`compress`] compress outbound responses.
+
+#[cfg(feature = "gzip")]
+use std::io::Write;
+use std::{
+    future::Future,
+    io::{self, Read},
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    extract::FromMesasge,
+    handler::{BoxHandlerService, HandlerService},
+    middleware::Layer,
+    request::{Frames, Request},
+    response::{IntoResponse, Response, Status},
+};
+
+/// Largest body [`Decompressed`] inflates to, guarding against compression bombs.
+pub const MAX_DECOMPRESSED_LEN: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "gzip")]
+    Zlib,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    pub fn compress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "gzip")]
+            Self::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::encode_all(body, 0),
+        }
+    }
+
+    /// Inflate `body`, failing if it grows past [`MAX_DECOMPRESSED_LEN`].
+    pub fn decompress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Box::new(flate2::read::GzDecoder::new(body)),
+            #[cfg(feature = "gzip")]
+            Self::Zlib => Box::new(flate2::read::ZlibDecoder::new(body)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::Decoder::new(body)?),
+        };
+
+        let mut inflated = Vec::new();
+        decoder
+            .take(MAX_DECOMPRESSED_LEN + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() as u64 > MAX_DECOMPRESSED_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed body is too large",
+            ));
+        }
+        Ok(inflated)
+    }
+}
+
+/// Names the encoding a [`Decompressed`] body is in.
+pub trait ContentEncoding {
+    const ENCODING: Encoding;
+}
+
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy)]
+pub enum Gzip {}
+
+#[cfg(feature = "gzip")]
+impl ContentEncoding for Gzip {
+    const ENCODING: Encoding = Encoding::Gzip;
+}
+
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy)]
+pub enum Zlib {}
+
+#[cfg(feature = "gzip")]
+impl ContentEncoding for Zlib {
+    const ENCODING: Encoding = Encoding::Zlib;
+}
+
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+pub enum Zstd {}
+
+#[cfg(feature = "zstd")]
+impl ContentEncoding for Zstd {
+    const ENCODING: Encoding = Encoding::Zstd;
+}
+
+/// Runs the inner extractor on the body decompressed with `C`.
+///
+/// A body that fails to decompress is rejected with [`Status::DecodeError`]. A compressed text
+/// body is handed to the inner extractor as a text frame.
+///
+/// ```ignore
+/// async fn trades(Decompressed(Json(trades), _): Decompressed<Gzip, Json<Vec<Trade>>>) {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct Decompressed<C, E>(pub E, pub PhantomData<C>);
+
+impl<S, C, E> FromMesasge<S> for Decompressed<C, E>
+where
+    C: ContentEncoding,
+    E: FromMesasge<S>,
+{
+    type Rejection = Response;
+    fn call(args: &Request, state: S) -> Result<Self, Self::Rejection> {
+        let encoding = C::ENCODING;
+        let body = encoding.decompress(args.as_bytes()).map_err(|err| {
+            Response::error(
+                Status::DecodeError,
+                format!("Failed to decompress {:?} payload: {}", encoding, err),
+            )
+            .with_source(err)
+        })?;
+
+        let inflated = args.with_body(Bytes::from(body));
+        E::call(&inflated, state)
+            .map(|inner| Self(inner, PhantomData))
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+/// Compresses a successful response, which is then sent as a binary frame.
+///
+/// ```ignore
+/// async fn snapshot() -> Compressed<Json<Snapshot>> {
+///     Compressed(Encoding::Gzip, Json(snapshot()))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compressed<R>(pub Encoding, pub R);
+
+impl<R> IntoResponse for Compressed<R>
+where
+    R: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let Self(encoding, inner) = self;
+        let response = inner.into_response();
+        Response::deferred(move |format| compress_response(encoding, response.finalize(format)))
+    }
+}
+
+fn compress_response(encoding: Encoding, response: Response) -> Response {
+    let compressible = matches!(response.frame, Frames::Text | Frames::Binary);
+    if !response.status.is_success() || !compressible || response.is_rejection() {
+        return response;
+    }
+
+    match encoding.compress(&response.body) {
+        Ok(body) => Response::binary(body),
+        Err(err) => Response::error(
+            Status::InternalError,
+            format!("Failed to compress {:?} response: {}", encoding, err),
+        )
+        .with_source(err),
+    }
+}
+
+/// Compress the successful text and binary responses of every route it wraps.
+pub fn compress(encoding: Encoding) -> CompressLayer {
+    CompressLayer { encoding }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressLayer {
+    encoding: Encoding,
+}
+
+impl<S> Layer<S> for CompressLayer
+where
+    S: Send + 'static,
+{
+    fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S> {
+        Arc::new(Compress {
+            encoding: self.encoding,
+            inner,
+        })
+    }
+}
+
+struct Compress<S> {
+    encoding: Encoding,
+    inner: BoxHandlerService<S>,
+}
+
+impl<S> HandlerService<S> for Compress<S> {
+    fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        let encoding = self.encoding;
+        let fut = self.inner.call(req, state);
+        Box::pin(async move { compress_response(encoding, fut.await) })
+    }
+}
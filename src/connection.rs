@@ -0,0 +1,432 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinSet,
+    time::{timeout, Instant},
+};
+use tokio_tungstenite::{
+    tungstenite::{self, http::Extensions, Message},
+    WebSocketStream,
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    extract::Sequence,
+    outbound::Outbound,
+    request::{CloseFrame, Request},
+    response::Status,
+    NextDoor,
+};
+
+/// Why a connection ended.
+#[derive(Debug)]
+pub enum Disconnect {
+    /// A handler returned [`Status::Reconnect`](crate::response::Status::Reconnect),
+    /// with the URL to reconnect to if the response carried one.
+    Reconnect(Option<String>),
+    /// The peer closed the connection or the stream ended, with the close frame if one was received.
+    Closed(Option<CloseFrame>),
+    /// Reading from or writing to the socket failed.
+    Error(tungstenite::Error),
+    /// Nothing was received within the keepalive timeout.
+    #[cfg(feature = "client")]
+    Timeout,
+    /// The outbound queue overflowed with
+    /// [`OverflowPolicy::Disconnect`](crate::OverflowPolicy::Disconnect).
+    #[cfg(feature = "client")]
+    Overflow,
+    /// The connection was closed because of a shutdown signal.
+    #[cfg(feature = "client")]
+    Shutdown,
+}
+
+/// How a connection runs the handlers of incoming messages.
+///
+/// Frames are read, and pings answered, while handlers run in every mode. A
+/// [`Status::Reconnect`](crate::response::Status::Reconnect) response stops the connection and
+/// cancels the handlers still running.
+#[derive(Clone, Default)]
+pub enum ExecutionMode {
+    /// One message at a time, in the order received.
+    #[default]
+    Sequential,
+    /// Up to `max_in_flight` messages at a time, in no particular order.
+    #[cfg(feature = "client")]
+    Concurrent { max_in_flight: usize },
+    /// Messages with the same key in the order received, different keys concurrently.
+    ///
+    /// Messages without a key are handled like in [`Concurrent`](Self::Concurrent). Received
+    /// messages waiting behind their key count towards `max_in_flight`.
+    #[cfg(feature = "client")]
+    Keyed { key: KeyFn, max_in_flight: usize },
+}
+
+/// Derives the ordering key of a message for [`ExecutionMode::Keyed`].
+pub type KeyFn = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+#[cfg(feature = "client")]
+impl ExecutionMode {
+    /// Order messages by the key `key` returns.
+    ///
+    /// ```ignore
+    /// client.with_execution_mode(ExecutionMode::keyed(256, |req| {
+    ///     req.as_str().ok()?.split(':').next().map(String::from)
+    /// }));
+    /// ```
+    pub fn keyed<F>(max_in_flight: usize, key: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Keyed {
+            key: Arc::new(key),
+            max_in_flight,
+        }
+    }
+
+    /// Order messages by a top-level field of their JSON body, such as a channel id.
+    pub fn json_field(max_in_flight: usize, field: &'static str) -> Self {
+        Self::keyed(max_in_flight, move |req| {
+            let value: serde_json::Value = serde_json::from_slice(req.as_bytes()).ok()?;
+            match value.get(field)? {
+                serde_json::Value::String(key) => Some(key.clone()),
+                key => Some(key.to_string()),
+            }
+        })
+    }
+}
+
+impl fmt::Debug for ExecutionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sequential => f.write_str("Sequential"),
+            #[cfg(feature = "client")]
+            Self::Concurrent { max_in_flight } => f
+                .debug_struct("Concurrent")
+                .field("max_in_flight", max_in_flight)
+                .finish(),
+            #[cfg(feature = "client")]
+            Self::Keyed { max_in_flight, .. } => f
+                .debug_struct("Keyed")
+                .field("max_in_flight", max_in_flight)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// When the last frame was received on a connection.
+#[derive(Clone)]
+pub(crate) struct Activity(Arc<std::sync::Mutex<Instant>>);
+
+impl Activity {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(std::sync::Mutex::new(Instant::now())))
+    }
+
+    fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn last(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+async fn handle_message<S>(
+    request: Request,
+    router: Arc<NextDoor<S>>,
+    tx: &Outbound,
+) -> Option<Disconnect>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    for response in router.dispatch(request).await {
+        // Routes finalize their own responses; this catches one built by hand.
+        let response = response.finalize(router.format());
+        if response.is_rejection() {
+            warn!(
+                status = ?response.status,
+                body = %String::from_utf8_lossy(&response.body),
+                "No route accepted message"
+            );
+
+            // A frame without routes is not the peer's mistake, an extractor rejection is.
+            if response.status != Status::NotFoundPath {
+                if let Some(reply) = router.error_reply(&response) {
+                    if tx.send(reply.into_ws_message(), false).await.is_err() {
+                        return Some(Disconnect::Closed(None));
+                    }
+                }
+            }
+            continue;
+        }
+
+        if response.status.is_reconnect() {
+            let url = response.try_to_string().ok().filter(|url| !url.is_empty());
+            return Some(Disconnect::Reconnect(url));
+        }
+
+        if response.status.is_success() {
+            debug!(frame = ?response.frame, "Sending response");
+            if tx.send(response.into_ws_message(), false).await.is_err() {
+                return Some(Disconnect::Closed(None));
+            }
+        } else if response.status != Status::NoContent {
+            warn!(
+                status = ?response.status,
+                body = %String::from_utf8_lossy(&response.body),
+                source = ?response.source(),
+                "Handler returned error response"
+            );
+
+            if let Some(reply) = router.error_reply(&response) {
+                if tx.send(reply.into_ws_message(), false).await.is_err() {
+                    return Some(Disconnect::Closed(None));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Received messages held while every handler slot is busy. Reading pauses once this many
+/// are waiting.
+const READ_AHEAD: usize = 256;
+
+/// Dispatch incoming messages until the connection ends.
+///
+/// The socket is read while handlers run, so frames count as activity and pings are answered
+/// even when a handler is slow. Each request carries a copy of `extensions` and its
+/// [`Sequence`] on the connection.
+pub(crate) async fn receive_messages<S, T>(
+    mut read: SplitStream<WebSocketStream<T>>,
+    router: Arc<NextDoor<S>>,
+    tx: Arc<Outbound>,
+    activity: Activity,
+    extensions: Extensions,
+    mode: ExecutionMode,
+) -> Disconnect
+where
+    S: Clone + Send + Sync + 'static,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (max_in_flight, key): (usize, Option<KeyFn>) = match mode {
+        ExecutionMode::Sequential => (1, None),
+        #[cfg(feature = "client")]
+        ExecutionMode::Concurrent { max_in_flight } => (max_in_flight.max(1), None),
+        #[cfg(feature = "client")]
+        ExecutionMode::Keyed { key, max_in_flight } => (max_in_flight.max(1), Some(key)),
+    };
+
+    let mut scheduler = Scheduler::new(router, tx, max_in_flight);
+    let mut close_frame = None;
+    let mut sequence = 0;
+    loop {
+        let msg = tokio::select! {
+            msg = read.next(), if scheduler.waiting.len() < READ_AHEAD => msg,
+            Some(finished) = scheduler.next() => {
+                match finished {
+                    Some(disconnect) => return disconnect,
+                    None => continue,
+                }
+            }
+        };
+        let Some(msg) = msg else { break };
+
+        match msg {
+            Ok(msg) => {
+                activity.touch();
+                if let Message::Close(Some(frame)) = &msg {
+                    close_frame = Some(CloseFrame {
+                        reason: frame.reason.to_string(),
+                        code: frame.code.into(),
+                    });
+                }
+
+                debug!(?msg, "Received WebSocket message");
+                sequence += 1;
+                let mut request = Request::from_ws_message(msg);
+                *request.extensions_mut() = extensions.clone();
+                request.extensions_mut().insert(Sequence(sequence));
+
+                let key = key.as_ref().and_then(|key| key(&request));
+                scheduler.push(key, request);
+            }
+            Err(e) => {
+                error!(error = %e, "Error receiving WebSocket message");
+                return Disconnect::Error(e);
+            }
+        }
+    }
+
+    // Let the handlers still running, and the messages still waiting, finish before the
+    // connection ends.
+    while let Some(finished) = scheduler.next().await {
+        if let Some(disconnect) = finished {
+            return disconnect;
+        }
+    }
+    Disconnect::Closed(close_frame)
+}
+
+/// Runs up to `max_in_flight` handlers at a time, keeping messages with the same key in order.
+struct Scheduler<S> {
+    router: Arc<NextDoor<S>>,
+    tx: Arc<Outbound>,
+    max_in_flight: usize,
+    running: JoinSet<(Option<String>, Option<Disconnect>)>,
+    /// Messages waiting for the running message with the same key.
+    queued: HashMap<String, VecDeque<Request>>,
+    /// Messages running or queued behind their key.
+    pending: usize,
+    /// Messages received while `max_in_flight` were pending, in order.
+    waiting: VecDeque<(Option<String>, Request)>,
+}
+
+impl<S> Scheduler<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn new(router: Arc<NextDoor<S>>, tx: Arc<Outbound>, max_in_flight: usize) -> Self {
+        Self {
+            router,
+            tx,
+            max_in_flight,
+            running: JoinSet::new(),
+            queued: HashMap::new(),
+            pending: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, key: Option<String>, request: Request) {
+        if self.pending >= self.max_in_flight {
+            self.waiting.push_back((key, request));
+            return;
+        }
+
+        self.pending += 1;
+        if let Some(key) = &key {
+            match self.queued.get_mut(key) {
+                Some(queue) => return queue.push_back(request),
+                None => {
+                    self.queued.insert(key.clone(), VecDeque::new());
+                }
+            }
+        }
+        self.spawn(key, request);
+    }
+
+    fn spawn(&mut self, key: Option<String>, request: Request) {
+        let router = self.router.clone();
+        let tx = self.tx.clone();
+        self.running
+            .spawn(async move { (key, handle_message(request, router, &tx).await) });
+    }
+
+    /// Wait for a handler to finish and start the next messages.
+    ///
+    /// Returns `None` once nothing is running, `Some(None)` when a handler finished without
+    /// ending the connection.
+    async fn next(&mut self) -> Option<Option<Disconnect>> {
+        let result = self.running.join_next().await?;
+        self.pending -= 1;
+
+        let (key, disconnect) = result.unwrap_or_else(|e| {
+            error!(error = %e, "Handler task join error");
+            (None, None)
+        });
+        if let Some(key) = key {
+            match self.queued.get_mut(&key).and_then(VecDeque::pop_front) {
+                Some(request) => self.spawn(Some(key), request),
+                None => {
+                    self.queued.remove(&key);
+                }
+            }
+        }
+        while self.pending < self.max_in_flight {
+            let Some((key, request)) = self.waiting.pop_front() else {
+                break;
+            };
+            self.push(key, request);
+        }
+        Some(disconnect)
+    }
+}
+
+/// Write queued messages until the queue closes or `close` resolves.
+///
+/// When `close` resolves, the messages already queued are written before the close message.
+/// A write that takes longer than `write_timeout` fails with [`io::ErrorKind::TimedOut`].
+pub(crate) async fn send_messages<T>(
+    mut write: SplitSink<WebSocketStream<T>, Message>,
+    queue: &Outbound,
+    close: impl Future<Output = Message>,
+    write_timeout: Option<Duration>,
+) -> Result<(), tungstenite::Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::pin!(close);
+    loop {
+        let msg = tokio::select! {
+            msg = queue.recv() => match msg {
+                Some(msg) => msg,
+                None => return Ok(()),
+            },
+            msg = &mut close => {
+                while let Some(queued) = queue.try_recv() {
+                    send(&mut write, queued, write_timeout).await?;
+                    queue.record_sent();
+                }
+                return send(&mut write, msg, write_timeout).await;
+            }
+        };
+
+        send(&mut write, msg, write_timeout).await?;
+        queue.record_sent();
+    }
+}
+
+async fn send<T>(
+    write: &mut SplitSink<WebSocketStream<T>, Message>,
+    msg: Message,
+    write_timeout: Option<Duration>,
+) -> Result<(), tungstenite::Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let sending = write.send(msg);
+    let result = match write_timeout {
+        Some(limit) => match timeout(limit, sending).await {
+            Ok(result) => result,
+            Err(_) => Err(tungstenite::Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "WebSocket write timed out",
+            ))),
+        },
+        None => sending.await,
+    };
+    if let Err(e) = &result {
+        error!(error = %e, "Error sending WebSocket message");
+    }
+    result
+}
+
+pub(crate) async fn shutdown() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!(error = %e, "Failed to listen for ctrl-c signal");
+    }
+}
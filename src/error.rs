@@ -1,6 +1,9 @@
 use std::string::FromUtf8Error;
 
-use crate::response::{IntoResponse, Response, Status};
+use crate::{
+    codec::BoxError,
+    response::{IntoResponse, Response, Status},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum HandlerError {
@@ -16,25 +19,151 @@ impl IntoResponse for HandlerError {
     }
 }
 
+/// Any extractor rejection.
+///
+/// Every per-extractor rejection converts into it, so custom extractors can use `?`.
 #[derive(Debug, thiserror::Error)]
 pub enum ExtractError {
     #[error("Failed to parse UTF-8 string: {0}")]
     FromStringError(#[from] FromUtf8Error),
     #[error("Failed to parse JSON: {0}")]
     JsonError(#[from] serde_json::Error),
+    #[error("Missing request extension: {0}")]
+    MissingExtension(&'static str),
+    #[error(transparent)]
+    DecodeError(#[from] DecodeRejection),
+    #[cfg(feature = "simd-json")]
+    #[error("Failed to parse JSON: {0}")]
+    SimdJsonError(simd_json::Error),
 }
 
 impl IntoResponse for ExtractError {
     fn into_response(self) -> Response {
         match self {
-            Self::FromStringError(e) => Response::error(
-                Status::FromStringError,
-                format!("Failed to parse request body as UTF-8: {}", e),
-            ),
-            Self::JsonError(e) => Response::error(
-                Status::JsonError,
-                format!("Failed to parse JSON payload: {}", e),
-            ),
+            Self::FromStringError(e) => StringRejection(e).into_response(),
+            Self::JsonError(e) => JsonRejection::InvalidJson(e).into_response(),
+            Self::MissingExtension(name) => MissingExtension(name).into_response(),
+            Self::DecodeError(rejection) => rejection.into_response(),
+            #[cfg(feature = "simd-json")]
+            Self::SimdJsonError(e) => {
+                JsonRejection::InvalidSimdJson(e, bytes::Bytes::new()).into_response()
+            }
+        }
+    }
+}
+
+/// Rejection of the `String` extractor: the body is not UTF-8.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to parse request body as UTF-8: {0}")]
+pub struct StringRejection(#[from] pub FromUtf8Error);
+
+impl StringRejection {
+    /// Byte offset of the first invalid UTF-8 sequence.
+    pub fn valid_up_to(&self) -> usize {
+        self.0.utf8_error().valid_up_to()
+    }
+}
+
+impl IntoResponse for StringRejection {
+    fn into_response(self) -> Response {
+        Response::error(Status::FromStringError, self.to_string()).with_source(self)
+    }
+}
+
+impl From<StringRejection> for ExtractError {
+    fn from(rejection: StringRejection) -> Self {
+        Self::FromStringError(rejection.0)
+    }
+}
+
+/// Rejection of the `Json` and `Close` extractors.
+#[derive(Debug, thiserror::Error)]
+pub enum JsonRejection {
+    #[error("Failed to parse JSON payload: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    /// Rejected by simd-json, with the body the error is located in.
+    #[cfg(feature = "simd-json")]
+    #[error("Failed to parse JSON payload: {0}")]
+    InvalidSimdJson(simd_json::Error, bytes::Bytes),
+}
+
+impl JsonRejection {
+    /// Line and column of the JSON error, starting at 1.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::InvalidJson(e) if e.line() > 0 => Some((e.line(), e.column())),
+            Self::InvalidJson(_) => None,
+            // Errors raised while deserializing into the target type carry no location.
+            #[cfg(feature = "simd-json")]
+            Self::InvalidSimdJson(e, _) if matches!(e.error(), simd_json::ErrorType::Serde(_)) => {
+                None
+            }
+            #[cfg(feature = "simd-json")]
+            Self::InvalidSimdJson(e, body) => {
+                let before = body.get(..e.index())?;
+                let line_start = before
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |i| i + 1);
+                let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+                Some((line, e.index() - line_start + 1))
+            }
+        }
+    }
+
+    /// Whether the body is valid JSON that does not match the expected type.
+    pub fn is_data(&self) -> bool {
+        match self {
+            Self::InvalidJson(e) => e.is_data(),
+            #[cfg(feature = "simd-json")]
+            Self::InvalidSimdJson(e, _) => e.is_data(),
         }
     }
 }
+
+impl IntoResponse for JsonRejection {
+    fn into_response(self) -> Response {
+        Response::error(Status::JsonError, self.to_string()).with_source(self)
+    }
+}
+
+impl From<JsonRejection> for ExtractError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::InvalidJson(e) => Self::JsonError(e),
+            #[cfg(feature = "simd-json")]
+            JsonRejection::InvalidSimdJson(e, _) => Self::SimdJsonError(e),
+        }
+    }
+}
+
+/// Rejection of extractors reading the request extensions, with the missing type name.
+#[derive(Debug, thiserror::Error)]
+#[error("Missing request extension: {0}")]
+pub struct MissingExtension(pub &'static str);
+
+impl IntoResponse for MissingExtension {
+    fn into_response(self) -> Response {
+        Response::error(Status::InternalError, self.to_string()).with_source(self)
+    }
+}
+
+impl From<MissingExtension> for ExtractError {
+    fn from(rejection: MissingExtension) -> Self {
+        Self::MissingExtension(rejection.0)
+    }
+}
+
+/// Rejection of the codec extractors such as `Typed` and `MsgPack`.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to decode {codec} payload: {source}")]
+pub struct DecodeRejection {
+    pub codec: &'static str,
+    pub source: BoxError,
+}
+
+impl IntoResponse for DecodeRejection {
+    fn into_response(self) -> Response {
+        Response::error(Status::DecodeError, self.to_string()).with_source(self)
+    }
+}
@@ -1,16 +1,113 @@
+use std::{
+    future::{ready, Future},
+    sync::Arc,
+};
+
+use bytes::Bytes;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-    error::ExtractError,
-    request::{CloseFrame, Request},
+    error::{ExtractError, JsonRejection, MissingExtension, StringRejection},
+    request::{CloseFrame, ConnectionInfo, Request},
     response::IntoResponse,
+    RouteRejection,
 };
 
+#[cfg(feature = "bincode")]
+pub use crate::codec::Bincode;
+#[cfg(feature = "cbor")]
+pub use crate::codec::Cbor;
+#[cfg(feature = "msgpack")]
+pub use crate::codec::MsgPack;
+#[cfg(feature = "protobuf")]
+pub use crate::codec::Protobuf;
+pub use crate::codec::Typed;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use crate::compression::Decompressed;
+
+/// Synchronous extractor that borrows the request, usable for any handler argument.
+///
+/// Every `FromMesasge` extractor is also a [`FromRequestParts`] extractor.
 pub trait FromMesasge<S>: Sized {
     type Rejection: IntoResponse + Send;
     fn call(args: &Request, state: S) -> Result<Self, Self::Rejection>;
 }
 
+/// Async extractor that borrows the request, usable for any handler argument.
+///
+/// ```ignore
+/// struct Session(User);
+///
+/// impl FromRequestParts<AppState> for Session {
+///     type Rejection = Status;
+///     async fn call(args: &Request, state: AppState) -> Result<Self, Self::Rejection> {
+///         let Some(Connection(info)) = args.extensions().get::<Connection>() else {
+///             return Err(Status::NotFound);
+///         };
+///         let sessions = state.sessions.lock().await;
+///         sessions.get(&info.id).cloned().map(Session).ok_or(Status::NotFound)
+///     }
+/// }
+/// ```
+pub trait FromRequestParts<S, M = private::ViaParts>: Sized {
+    type Rejection: IntoResponse + Send;
+    fn call(args: &Request, state: S)
+        -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+/// Async extractor that takes ownership of the request, usable only for the last handler argument.
+///
+/// Every [`FromRequestParts`] extractor is also a `FromRequest` extractor.
+pub trait FromRequest<S, M = private::ViaRequest>: Sized {
+    type Rejection: IntoResponse + Send;
+    fn call(args: Request, state: S) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+mod private {
+    #[derive(Debug, Clone, Copy)]
+    pub enum ViaMessage {}
+
+    #[derive(Debug, Clone, Copy)]
+    pub enum ViaParts {}
+
+    #[derive(Debug, Clone, Copy)]
+    pub enum ViaRequest {}
+}
+
+impl<S, T> FromRequestParts<S, private::ViaMessage> for T
+where
+    T: FromMesasge<S> + Send,
+{
+    type Rejection = <T as FromMesasge<S>>::Rejection;
+    fn call(
+        args: &Request,
+        state: S,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
+        ready(<T as FromMesasge<S>>::call(args, state))
+    }
+}
+
+impl<S, T, M> FromRequest<S, (private::ViaParts, M)> for T
+where
+    T: FromRequestParts<S, M>,
+    S: Send,
+{
+    type Rejection = <T as FromRequestParts<S, M>>::Rejection;
+    async fn call(args: Request, state: S) -> Result<Self, Self::Rejection> {
+        <T as FromRequestParts<S, M>>::call(&args, state).await
+    }
+}
+
+impl<S> FromRequest<S> for Request
+where
+    S: Send,
+{
+    type Rejection = ExtractError;
+    async fn call(args: Request, _: S) -> Result<Self, Self::Rejection> {
+        Ok(args)
+    }
+}
+
 #[doc = "Extract of NextDoor"]
 #[derive(Debug, Clone)]
 pub struct Json<T>(pub T);
@@ -28,15 +125,32 @@ impl<T, S> FromMesasge<S> for Json<T>
 where
     T: DeserializeOwned,
 {
-    type Rejection = ExtractError;
+    type Rejection = JsonRejection;
+    #[cfg(not(feature = "simd-json"))]
     fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
-        let result = args
-            .try_to_string()
-            .map_err(ExtractError::FromStringError)?;
+        Ok(Self(serde_json::from_slice(args.as_bytes())?))
+    }
+
+    /// simd-json parses in place, so the body is copied into a per-thread scratch buffer that,
+    /// like the parser buffers, is reused across messages. The rejection keeps the body to
+    /// locate the error when [`JsonRejection::position`] is called.
+    #[cfg(feature = "simd-json")]
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        use std::cell::RefCell;
 
-        let result: T = serde_json::from_str(&result).map_err(ExtractError::JsonError)?;
+        thread_local! {
+            static SCRATCH: RefCell<(Vec<u8>, simd_json::Buffers)> = RefCell::default();
+        }
 
-        Ok(Self(result))
+        let parsed = SCRATCH.with(|scratch| {
+            let (body, buffers) = &mut *scratch.borrow_mut();
+            body.clear();
+            body.extend_from_slice(args.as_bytes());
+            simd_json::serde::from_slice_with_buffers(body, buffers)
+        });
+        parsed
+            .map(Self)
+            .map_err(|err| JsonRejection::InvalidSimdJson(err, args.body()))
     }
 }
 
@@ -54,28 +168,105 @@ where
     }
 }
 
+/// The connection the request arrived on.
+///
+/// Set by the client and the server; extracting it from a request dispatched by hand
+/// fails with [`ExtractError::MissingExtension`].
+#[derive(Debug, Clone)]
+pub struct Connection(pub Arc<ConnectionInfo>);
+
+impl<S> FromRequestParts<S> for Connection
+where
+    S: Send,
+{
+    type Rejection = MissingExtension;
+    async fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        args.extensions()
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingExtension("Connection"))
+    }
+}
+
+/// Position of the message on its connection, starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sequence(pub u64);
+
+impl<S> FromRequestParts<S> for Sequence
+where
+    S: Send,
+{
+    type Rejection = MissingExtension;
+    async fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        args.extensions()
+            .get::<Self>()
+            .copied()
+            .ok_or(MissingExtension("Sequence"))
+    }
+}
+
+/// A value from the request extensions, put there by a layer or by
+/// [`NextDoor::map_request`](crate::NextDoor::map_request).
+///
+/// As a layer, `Extension(value)` inserts a clone of `value` into every request it wraps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Extension<T>(pub T);
+
+impl<T, S> FromMesasge<S> for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    type Rejection = MissingExtension;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        args.extensions()
+            .get::<T>()
+            .cloned()
+            .map(Self)
+            .ok_or(MissingExtension(std::any::type_name::<T>()))
+    }
+}
+
+/// Why the routes tried before the [fallback](crate::NextDoor::fallback) rejected the message.
+///
+/// Empty outside the fallback, and when no route exists for the frame.
+#[derive(Debug, Clone, Default)]
+pub struct Rejections(pub Vec<RouteRejection>);
+
+impl<S> FromMesasge<S> for Rejections {
+    type Rejection = ExtractError;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        Ok(args.extensions().get::<Self>().cloned().unwrap_or_default())
+    }
+}
+
 /// wrapped in Arc<S>
 #[doc = "Extract of NextDoor"]
 #[derive(Debug, Clone)]
 pub struct Close(pub Option<CloseFrame>);
 
 impl<S> FromMesasge<S> for Close {
-    type Rejection = ExtractError;
+    type Rejection = JsonRejection;
     fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
         if args.is_empty() {
             Ok(Self(None))
         } else {
-            Ok(Self(Some(
-                serde_json::from_str(&args.try_to_string().unwrap()).unwrap(),
-            )))
+            Ok(Self(Some(serde_json::from_slice(args.as_bytes())?)))
         }
     }
 }
 
-impl<S> FromMesasge<S> for String {
+/// The body without copying it.
+impl<S> FromMesasge<S> for Bytes {
     type Rejection = ExtractError;
     fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
-        args.try_to_string().map_err(ExtractError::FromStringError)
+        Ok(args.body())
+    }
+}
+
+impl<S> FromMesasge<S> for String {
+    type Rejection = StringRejection;
+    fn call(args: &Request, _: S) -> Result<Self, Self::Rejection> {
+        Ok(args.try_to_string()?)
     }
 }
 
@@ -1,8 +1,10 @@
 #![allow(non_snake_case)]
-use std::{future::Future, marker::PhantomData, pin::Pin};
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
 
 use crate::{
-    extract::FromMesasge,
+    codec::Format,
+    extract::{FromRequest, FromRequestParts},
+    middleware::Layer,
     request::Request,
     response::{IntoResponse, Response},
 };
@@ -10,6 +12,22 @@ use crate::{
 pub trait Handler<T, S>: Clone + Send + Sync + 'static {
     type Future: Future<Output = Response> + Send + 'static;
     fn call(self, args: Request, state: S) -> Self::Future;
+
+    /// Wrap only this handler with a middleware layer.
+    ///
+    /// ```ignore
+    /// router.text(handler.layer(from_fn(auth)));
+    /// ```
+    fn layer<L>(self, layer: L) -> Layered<Self, L, T>
+    where
+        L: Layer<S> + Clone,
+    {
+        Layered {
+            handler: self,
+            layer,
+            _marker: PhantomData,
+        }
+    }
 }
 
 impl<F, Fut, S, Res> Handler<(), S> for F
@@ -21,45 +39,212 @@ where
 {
     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-    fn call(self, _: Request, _: S) -> Self::Future {
+    fn call(self, req: Request, _: S) -> Self::Future {
+        let format = Format::of(&req);
         let fut = self();
-        Box::pin(async move { fut.await.into_response() })
+        Box::pin(async move { fut.await.into_response().finalize(format) })
     }
 }
 
+/// Handlers take up to 16 extractors. All but the last implement [`FromRequestParts`];
+/// the last may be any [`FromRequest`] extractor, which includes every `FromRequestParts` one.
+///
+/// Extractors run in argument order, each awaited before the next starts. A rejection is
+/// returned as a [`Response::into_rejection`] so the router moves on to the next route.
 macro_rules! impl_handler {
-    ($($ty:ident),*) => {
-        impl<F, Fut, $($ty,)* S, Res> Handler<($($ty,)*), S> for F
+    ([$(($ty:ident, $m:ident)),*], $last:ident) => {
+        impl<F, Fut, $($ty, $m,)* $last, S, Res, M> Handler<(M, $($m,)* $($ty,)* $last,), S> for F
         where
-            F: Fn($($ty,)*) -> Fut + Clone + Send + Sync + 'static,
+            F: Fn($($ty,)* $last) -> Fut + Clone + Send + Sync + 'static,
             Fut: Future<Output = Res> + Send + 'static,
-         $( $ty: FromMesasge<S> + Send + Sync + 'static, )*
+         $( $ty: FromRequestParts<S, $m> + Send + Sync + 'static, )*
+            $last: FromRequest<S, M> + Send + Sync + 'static,
             Res: IntoResponse,
             S: Clone + Send + Sync + 'static,
         {
             type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
             fn call(self, req: Request, state: S) -> Self::Future {
-             $( let $ty = match $ty::call(&req, state.clone()) {
-                              Ok(e) => e,
-                              Err(e) => return Box::pin(async move { e.into_response() }),
-                          };
-             )*
-                let fut = self($($ty,)*);
-                Box::pin(async move { fut.await.into_response() })
+                Box::pin(async move {
+                    let format = Format::of(&req);
+                 $( let $ty = match <$ty as FromRequestParts<S, $m>>::call(&req, state.clone()).await {
+                                  Ok(e) => e,
+                                  Err(e) => return e.into_response().into_rejection(),
+                              };
+                 )*
+                    let $last = match <$last as FromRequest<S, M>>::call(req, state).await {
+                        Ok(e) => e,
+                        Err(e) => return e.into_response().into_rejection(),
+                    };
+                    self($($ty,)* $last).await.into_response().finalize(format)
+                })
             }
         }
     };
 }
 
-impl_handler!(T1);
-impl_handler!(T1, T2);
+impl_handler!([], T1);
+impl_handler!([(T1, M1)], T2);
+impl_handler!([(T1, M1), (T2, M2)], T3);
+impl_handler!([(T1, M1), (T2, M2), (T3, M3)], T4);
+impl_handler!([(T1, M1), (T2, M2), (T3, M3), (T4, M4)], T5);
+impl_handler!([(T1, M1), (T2, M2), (T3, M3), (T4, M4), (T5, M5)], T6);
+impl_handler!(
+    [(T1, M1), (T2, M2), (T3, M3), (T4, M4), (T5, M5), (T6, M6)],
+    T7
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7)
+    ],
+    T8
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8)
+    ],
+    T9
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9)
+    ],
+    T10
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10)
+    ],
+    T11
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10),
+        (T11, M11)
+    ],
+    T12
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10),
+        (T11, M11),
+        (T12, M12)
+    ],
+    T13
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10),
+        (T11, M11),
+        (T12, M12),
+        (T13, M13)
+    ],
+    T14
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10),
+        (T11, M11),
+        (T12, M12),
+        (T13, M13),
+        (T14, M14)
+    ],
+    T15
+);
+impl_handler!(
+    [
+        (T1, M1),
+        (T2, M2),
+        (T3, M3),
+        (T4, M4),
+        (T5, M5),
+        (T6, M6),
+        (T7, M7),
+        (T8, M8),
+        (T9, M9),
+        (T10, M10),
+        (T11, M11),
+        (T12, M12),
+        (T13, M13),
+        (T14, M14),
+        (T15, M15)
+    ],
+    T16
+);
 
 // Type Erasure
 pub trait HandlerService<S> {
     fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>>;
 }
 
+pub type BoxHandlerService<S> = Arc<dyn HandlerService<S> + Send + Sync>;
+
 pub struct ExtractorHandler<H, T, S>
 where
     H: Handler<T, S>,
@@ -82,3 +267,42 @@ where
         Box::pin(fut)
     }
 }
+
+/// A handler wrapped in a per-route layer, see [`Handler::layer`].
+pub struct Layered<H, L, T> {
+    handler: H,
+    layer: L,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<H, L, T> Clone for Layered<H, L, T>
+where
+    H: Clone,
+    L: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            layer: self.layer.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, L, T, S> Handler<T, S> for Layered<H, L, T>
+where
+    H: Handler<T, S>,
+    L: Layer<S> + Clone,
+    T: Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: Request, state: S) -> Self::Future {
+        let service = self.layer.layer(Arc::new(ExtractorHandler {
+            handler: self.handler,
+            _marker: PhantomData,
+        }));
+        service.call(req, state)
+    }
+}
@@ -37,6 +37,8 @@
 //!         .text(param_string_return_ok)
 //!         .text(param_string_return_err)
 //!         .text(param_json_return_json)
+//!         // dispatch on a JSON field without trial parsing
+//!         .on("type", "user", param_json_return_unit)
 //!         // .text(param_app_store_return_unit)
 //!         // .text(param_json_app_store_return_unit);
 //!         .binary(binary)
@@ -49,6 +51,10 @@
 //!
 //!     // Features = "client"
 //!     // nextdoor::connect(router, "url").run().await.unwrap();
+//!
+//!     // Features = "server"
+//!     // let listener = tokio::net::TcpListener::bind("127.0.0.1:9000").await.unwrap();
+//!     // nextdoor::serve(router, listener).run().await;
 //! }
 //!
 //!
@@ -128,9 +134,13 @@
 //! }
 //! ```
 
+pub mod codec;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub mod compression;
 pub mod error;
 pub mod extract;
 pub mod handler;
+pub mod middleware;
 pub mod request;
 pub mod response;
 
@@ -139,19 +149,111 @@ mod client;
 #[cfg(feature = "client")]
 pub use client::*;
 
-use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+#[cfg(any(feature = "client", feature = "server"))]
+mod connection;
+#[cfg(any(feature = "client", feature = "server"))]
+mod outbound;
+#[cfg(feature = "client")]
+pub use outbound::{OverflowPolicy, QueueMetrics};
+
+#[cfg(feature = "client")]
+mod proxy;
+#[cfg(feature = "client")]
+pub use proxy::{Proxy, ProxyError};
+
+#[cfg(feature = "client")]
+mod reconnect;
+#[cfg(feature = "client")]
+pub use reconnect::*;
+
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "server")]
+pub use server::*;
+
+use std::{collections::HashMap, future::Future, marker::PhantomData, pin::Pin, sync::Arc};
 
-use handler::{ExtractorHandler, Handler, HandlerService};
+use handler::{BoxHandlerService, ExtractorHandler, Handler, HandlerService};
+use middleware::Layer;
 use request::{Frames, Request};
 use response::{Response, Status};
+use serde_json::Value;
 use tracing::{debug, instrument};
 
 pub struct EntryRoute<S> {
-    handler: Box<dyn HandlerService<S> + Send + Sync>,
+    handler: BoxHandlerService<S>,
+}
+
+impl<S> EntryRoute<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn new<P, F>(handler: F) -> Self
+    where
+        F: Handler<P, S> + Clone + Send + Sync + 'static,
+        P: Send + Sync + 'static,
+    {
+        Self {
+            handler: Arc::new(ExtractorHandler {
+                handler,
+                _marker: PhantomData,
+            }),
+        }
+    }
+}
+
+/// Text routes keyed on the value of a single JSON field.
+///
+/// `key` is either a top level field name (`"type"`) or a JSON pointer (`"/data/type"`).
+struct Discriminator<S> {
+    key: String,
+    routes: HashMap<String, Vec<EntryRoute<S>>>,
+}
+
+impl<S> Discriminator<S> {
+    fn new(key: String) -> Self {
+        Self {
+            key,
+            routes: HashMap::new(),
+        }
+    }
+
+    fn lookup(&self, json: &Value) -> Option<&Vec<EntryRoute<S>>> {
+        let value = if self.key.starts_with('/') {
+            json.pointer(&self.key)?
+        } else {
+            json.get(&self.key)?
+        };
+
+        match value {
+            Value::String(value) => self.routes.get(value),
+            Value::Number(_) | Value::Bool(_) => self.routes.get(&value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+type MapRequest = Arc<dyn Fn(&mut Request) + Send + Sync>;
+type DeadLetterSink = Arc<dyn Fn(DeadLetter) + Send + Sync>;
+type MapError = Arc<dyn Fn(&Response) -> Option<Response> + Send + Sync>;
+
+/// A message no route accepted, handed to [`NextDoor::dead_letter`].
+pub struct DeadLetter {
+    pub request: Request,
+    /// Why each tried route, and the fallback if there is one, rejected the message.
+    /// Empty when no route exists for the frame.
+    pub rejections: Vec<RouteRejection>,
 }
 
 pub struct NextDoor<S = ()> {
     route: HashMap<Frames, Vec<EntryRoute<S>>>,
+    discriminators: Vec<Discriminator<S>>,
+    map_request: Vec<MapRequest>,
+    fallback: Option<EntryRoute<S>>,
+    dead_letter: Option<DeadLetterSink>,
+    map_error: Option<MapError>,
+    format: Option<codec::Format>,
+    fan_out: bool,
     state: S,
 }
 
@@ -165,6 +267,13 @@ impl NextDoor<Arc<()>> {
     pub fn new() -> Self {
         Self {
             route: HashMap::new(),
+            discriminators: Vec::new(),
+            map_request: Vec::new(),
+            fallback: None,
+            dead_letter: None,
+            map_error: None,
+            format: None,
+            fan_out: false,
             state: Arc::new(()),
         }
     }
@@ -200,59 +309,557 @@ where
     pub fn with_state(state: S) -> NextDoor<S> {
         NextDoor {
             route: HashMap::new(),
+            discriminators: Vec::new(),
+            map_request: Vec::new(),
+            fallback: None,
+            dead_letter: None,
+            map_error: None,
+            format: None,
+            fan_out: false,
             state,
         }
     }
 
-    fn route<P, F>(&mut self, frame: Frames, handler: F) -> &mut Self
+    /// Register a text handler for JSON messages whose `key` field equals `value`.
+    ///
+    /// `key` is a top level field name or a JSON pointer starting with `/`.
+    /// String, number and boolean fields are compared against `value`.
+    ///
+    /// ```ignore
+    /// router
+    ///     .on("type", "chat.message", chat_message)
+    ///     .on("/data/event", "trade", trade);
+    /// ```
+    pub fn on<P, F, K, V>(&mut self, key: K, value: V, handler: F) -> &mut Self
     where
         F: Handler<P, S> + Clone + Send + Sync + 'static,
         P: Send + Sync + 'static,
+        K: Into<String>,
+        V: Into<String>,
     {
-        self.route.entry(frame).or_default().push(EntryRoute {
-            handler: Box::new(ExtractorHandler {
-                handler,
-                _marker: PhantomData,
+        self.discriminator(key.into())
+            .routes
+            .entry(value.into())
+            .or_default()
+            .push(EntryRoute::new(handler));
+        self
+    }
+
+    /// Add every route of `other` to this router.
+    ///
+    /// The routes keep the state and [`codec`](Self::codec) of `other`, so routers with different
+    /// state types can be merged. Its [`map_request`](Self::map_request) functions run only for
+    /// its own routes, and its fallback, dead-letter sink and error mapping are used if this
+    /// router has none.
+    ///
+    /// ```ignore
+    /// let mut router = NextDoor::with_state(app_state);
+    /// router.merge(chat::router()).merge(trades::router(db));
+    /// ```
+    pub fn merge<S2>(&mut self, other: NextDoor<S2>) -> &mut Self
+    where
+        S2: Clone + Send + Sync + 'static,
+    {
+        let state = other.state;
+        let format = other.format;
+        let map_request: Arc<[MapRequest]> = other.map_request.into();
+        let bind = |route: EntryRoute<S2>| EntryRoute {
+            handler: Arc::new(BindState {
+                inner: route.handler,
+                state: state.clone(),
+                format,
+                map_request: map_request.clone(),
             }),
-        });
+        };
+
+        for (frame, routes) in other.route {
+            self.route
+                .entry(frame)
+                .or_default()
+                .extend(routes.into_iter().map(bind));
+        }
+
+        for discriminator in other.discriminators {
+            let target = self.discriminator(discriminator.key);
+            for (value, routes) in discriminator.routes {
+                target
+                    .routes
+                    .entry(value)
+                    .or_default()
+                    .extend(routes.into_iter().map(bind));
+            }
+        }
+
+        if self.fallback.is_none() {
+            self.fallback = other.fallback.map(bind);
+        }
+        if self.dead_letter.is_none() {
+            self.dead_letter = other.dead_letter;
+        }
+        if self.map_error.is_none() {
+            self.map_error = other.map_error;
+        }
+        self
+    }
+
+    /// Dispatch text messages whose `key` field equals `value` to `router`.
+    ///
+    /// `key` and `value` match like [`on`](Self::on). The nested router keeps its own state,
+    /// routes and [`map_request`](Self::map_request) functions, and sees the whole message.
+    /// A message none of its routes accept falls through to the next route of this router;
+    /// its fallback and dead-letter sink are not used.
+    ///
+    /// ```ignore
+    /// router
+    ///     .nest("channel", "orders", orders::router(db))
+    ///     .nest("channel", "book", book::router());
+    /// ```
+    pub fn nest<S2, K, V>(&mut self, key: K, value: V, router: NextDoor<S2>) -> &mut Self
+    where
+        S2: Clone + Send + Sync + 'static,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.discriminator(key.into())
+            .routes
+            .entry(value.into())
+            .or_default()
+            .push(EntryRoute {
+                handler: Arc::new(Nested {
+                    router: Arc::new(router),
+                }),
+            });
+        self
+    }
+
+    /// Like [`nest`](Self::nest), but the nested routes get their state from the state of
+    /// this router, through `state`, instead of the state `router` was built with.
+    ///
+    /// ```ignore
+    /// router.nest_with_state("channel", "orders", orders::router(), |app: &AppState| {
+    ///     app.orders.clone()
+    /// });
+    /// ```
+    pub fn nest_with_state<S2, K, V, F>(
+        &mut self,
+        key: K,
+        value: V,
+        router: NextDoor<S2>,
+        state: F,
+    ) -> &mut Self
+    where
+        S2: Clone + Send + Sync + 'static,
+        K: Into<String>,
+        V: Into<String>,
+        F: Fn(&S) -> S2 + Send + Sync + 'static,
+    {
+        self.discriminator(key.into())
+            .routes
+            .entry(value.into())
+            .or_default()
+            .push(EntryRoute {
+                handler: Arc::new(NestedWithState {
+                    router: Arc::new(router),
+                    state: Arc::new(state),
+                }),
+            });
+        self
+    }
+
+    fn discriminator(&mut self, key: String) -> &mut Discriminator<S> {
+        let index = match self.discriminators.iter().position(|d| d.key == key) {
+            Some(index) => index,
+            None => {
+                self.discriminators.push(Discriminator::new(key));
+                self.discriminators.len() - 1
+            }
+        };
+
+        &mut self.discriminators[index]
+    }
+
+    /// Wrap every route registered so far with `layer`.
+    ///
+    /// Routes added after this call are not wrapped, so call it once all routes are registered.
+    ///
+    /// ```ignore
+    /// router
+    ///     .text(handler)
+    ///     .layer(middleware::from_fn(timing))
+    ///     .layer(middleware::catch_panic());
+    /// ```
+    pub fn layer<L>(&mut self, layer: L) -> &mut Self
+    where
+        L: Layer<S>,
+    {
+        let routes = self
+            .route
+            .values_mut()
+            .chain(
+                self.discriminators
+                    .iter_mut()
+                    .flat_map(|d| d.routes.values_mut()),
+            )
+            .flatten()
+            .chain(self.fallback.as_mut());
+
+        for route in routes {
+            route.handler = layer.layer(route.handler.clone());
+        }
+        self
+    }
+
+    /// Wrap every route registered so far with a [`tower::Layer`].
+    ///
+    /// As with [`layer`](Self::layer), routes added after this call are not wrapped.
+    #[cfg(feature = "tower")]
+    pub fn tower_layer<L>(&mut self, layer: L) -> &mut Self
+    where
+        middleware::TowerLayer<L>: Layer<S>,
+    {
+        self.layer(middleware::TowerLayer::new(layer))
+    }
+
+    /// Run `f` on every request before it is dispatched.
+    ///
+    /// Unlike a layer, `f` runs once per message rather than once per tried route, so it is the
+    /// place to decode a shared envelope and hand the result to handlers as an
+    /// [`Extension`](extract::Extension).
+    ///
+    /// ```ignore
+    /// router.map_request(|req| {
+    ///     if let Ok(envelope) = serde_json::from_slice::<Envelope>(&req.body()) {
+    ///         req.extensions_mut().insert(envelope);
+    ///     }
+    /// });
+    /// ```
+    pub fn map_request<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&mut Request) + Send + Sync + 'static,
+    {
+        self.map_request.push(Arc::new(f));
+        self
+    }
+
+    fn route<P, F>(&mut self, frame: Frames, handler: F) -> &mut Self
+    where
+        F: Handler<P, S> + Clone + Send + Sync + 'static,
+        P: Send + Sync + 'static,
+    {
+        self.route
+            .entry(frame)
+            .or_default()
+            .push(EntryRoute::new(handler));
+        self
+    }
+
+    /// Handle messages that no route accepts: frames without routes, and messages every
+    /// matching route rejected.
+    ///
+    /// Without a fallback the response for such a message is a rejection, see
+    /// [`Response::is_rejection`]. The fallback can see why the routes rejected the message
+    /// with the [`Rejections`](extract::Rejections) extractor.
+    pub fn fallback<P, F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Handler<P, S> + Clone + Send + Sync + 'static,
+        P: Send + Sync + 'static,
+    {
+        self.fallback = Some(EntryRoute::new(handler));
+        self
+    }
+
+    /// Record messages that neither a route nor the fallback accepted.
+    ///
+    /// ```ignore
+    /// let (tx, rx) = std::sync::mpsc::channel();
+    /// router.dead_letter(move |letter| {
+    ///     tx.send(letter).ok();
+    /// });
+    /// ```
+    pub fn dead_letter<F>(&mut self, sink: F) -> &mut Self
+    where
+        F: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        self.dead_letter = Some(Arc::new(sink));
         self
     }
 
+    /// Set the format [`Typed`](codec::Typed) bodies are decoded from and encoded in.
+    ///
+    /// Defaults to JSON. A nested router without a format of its own uses the format of the
+    /// router it is nested in.
+    pub fn codec(&mut self, format: codec::Format) -> &mut Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Choose what the client and the server send back for error responses.
+    ///
+    /// By default error responses are only logged. `f` is called with every error response a
+    /// handler returns, and with the extractor rejection of a message no route accepted; the
+    /// response it returns, if any, is sent to the peer.
+    ///
+    /// ```ignore
+    /// // reply {"error":{"code":400,"status":"JsonError","message":"..."}}
+    /// router.map_error(|response| Some(response.to_json_error()));
+    /// ```
+    pub fn map_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Response) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.map_error = Some(Arc::new(f));
+        self
+    }
+
+    /// The reply for an error response, see [`map_error`](Self::map_error).
+    pub fn error_reply(&self, response: &Response) -> Option<Response> {
+        let reply = self.map_error.as_ref().and_then(|f| f(response))?;
+        Some(reply.finalize(self.format()))
+    }
+
+    /// The format of [`codec`](Self::codec), JSON by default.
+    pub(crate) fn format(&self) -> codec::Format {
+        self.format.unwrap_or_default()
+    }
+
+    /// Run every matching route that accepts a message instead of stopping at the first one.
+    ///
+    /// The responses are collected by [`dispatch`](Self::dispatch), which the client and the
+    /// server use to reply; [`handler`](Self::handler) still returns only the first.
+    pub fn fan_out(&mut self) -> &mut Self {
+        self.fan_out = true;
+        self
+    }
+
+    /// Dispatch a message to the first route that accepts it.
+    ///
+    /// Routes are tried in registration order. A route whose extractors reject the message is
+    /// skipped; the response of the first route that runs its handler is returned as is.
     #[instrument(skip(self, req), fields(path = ?req.path), level = "debug")]
-    pub async fn handler(&self, req: Request) -> Response {
-        let routes = match self.route.get(&req.path) {
-            Some(route) => route,
+    pub async fn handler(&self, mut req: Request) -> Response {
+        self.prepare(&mut req);
+        self.route_request(req, self.state.clone(), true).await
+    }
+
+    /// Try the routes for `req` in order.
+    ///
+    /// The fallback and the dead-letter sink only run for the router the message was handed
+    /// to, not for nested routers, whose rejection is returned so the parent can try its
+    /// next route.
+    async fn route_request(&self, req: Request, state: S, top_level: bool) -> Response {
+        let routes = self.candidates(&req);
+        let unhandled = top_level && (self.fallback.is_some() || self.dead_letter.is_some());
+        let mut rejections = Vec::new();
+        if let Some((last, rest)) = routes.split_last() {
+            for route in rest {
+                let response = route.handler.call(req.clone(), state.clone()).await;
+                if !response.is_rejection() {
+                    return response;
+                }
+                rejections.push(response);
+            }
+
+            // Only the fallback and the dead-letter sink need the request after the last route.
+            if !unhandled {
+                return last.handler.call(req, state).await;
+            }
+
+            let response = last.handler.call(req.clone(), state.clone()).await;
+            if !response.is_rejection() {
+                return response;
+            }
+            rejections.push(response);
+        }
+
+        if unhandled {
+            self.unhandled(req, rejections).await
+        } else {
+            not_found(&req)
+        }
+    }
+
+    /// Dispatch a message and collect the responses to send back.
+    ///
+    /// In [`fan_out`](Self::fan_out) mode every accepting route runs, otherwise this is
+    /// [`handler`](Self::handler) with a single response.
+    pub async fn dispatch(&self, mut req: Request) -> Vec<Response> {
+        if !self.fan_out {
+            return vec![self.handler(req).await];
+        }
+
+        self.prepare(&mut req);
+
+        let mut responses = Vec::new();
+        let mut rejections = Vec::new();
+        for route in self.candidates(&req) {
+            let response = route.handler.call(req.clone(), self.state.clone()).await;
+            if response.is_rejection() {
+                rejections.push(response);
+            } else {
+                responses.push(response);
+            }
+        }
+
+        if responses.is_empty() {
+            responses.push(self.unhandled(req, rejections).await);
+        }
+        responses
+    }
+
+    fn prepare(&self, req: &mut Request) {
+        if let Some(format) = self.format {
+            req.extensions_mut().insert(format);
+        }
+        for f in &self.map_request {
+            f(req);
+        }
+    }
+
+    fn candidates(&self, req: &Request) -> &[EntryRoute<S>] {
+        if let Some(routes) = self.discriminated(req) {
+            return routes;
+        }
+
+        match self.route.get(&req.path) {
+            Some(routes) => routes,
             None => {
                 debug!("No handler found for frame type");
-                return Response {
-                    status: Status::NotFountPath,
-                    body: String::from_utf8(req.to_vec()).unwrap(),
-                };
+                &[]
             }
-        };
-        let mut last = Response {
-            status: Status::NotFound,
-            body: "".to_string(),
-        };
-        for route in routes.iter() {
-            let result = route.handler.call(req.clone(), self.state.clone()).await;
-            if result.status == Status::OK {
-                return result;
+        }
+    }
+
+    fn discriminated(&self, req: &Request) -> Option<&Vec<EntryRoute<S>>> {
+        if req.path != Frames::Text || self.discriminators.is_empty() {
+            return None;
+        }
+
+        let json: Value = serde_json::from_slice(req.as_bytes()).ok()?;
+        let routes = self.discriminators.iter().find_map(|d| d.lookup(&json));
+        if routes.is_none() {
+            debug!("No discriminator matched text message");
+        }
+        routes
+    }
+
+    async fn unhandled(&self, mut req: Request, mut rejections: Vec<Response>) -> Response {
+        if let Some(fallback) = &self.fallback {
+            let reasons = rejections.iter().map(RouteRejection::new).collect();
+            req.extensions_mut().insert(extract::Rejections(reasons));
+
+            let response = fallback.handler.call(req.clone(), self.state.clone()).await;
+            if !response.is_rejection() {
+                return response;
             }
-            last = result;
+            rejections.push(response);
+        }
+
+        let reasons = rejections.iter().map(RouteRejection::new).collect();
+        let response = rejections.pop().unwrap_or_else(|| not_found(&req));
+
+        if let Some(sink) = &self.dead_letter {
+            req.extensions_mut().remove::<extract::Rejections>();
+            sink(DeadLetter {
+                request: req,
+                rejections: reasons,
+            });
         }
 
-        Response {
-            status: Status::NotFound,
-            body: last.body,
+        response.into_rejection()
+    }
+}
+
+fn not_found(req: &Request) -> Response {
+    Response::with_frame(Status::NotFoundPath, req.path.clone(), req.body()).into_rejection()
+}
+
+/// Why a route did not accept a message.
+#[derive(Debug, Clone)]
+pub struct RouteRejection {
+    pub status: Status,
+    pub reason: String,
+    /// The extractor rejection, see [`Response::source`].
+    pub source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+}
+
+impl RouteRejection {
+    fn new(response: &Response) -> Self {
+        Self {
+            status: response.status,
+            reason: String::from_utf8_lossy(&response.body).into_owned(),
+            source: response.shared_source(),
+        }
+    }
+}
+
+/// A route of a merged router, called with that router's state, format and `map_request`
+/// functions.
+struct BindState<S> {
+    inner: BoxHandlerService<S>,
+    state: S,
+    format: Option<codec::Format>,
+    map_request: Arc<[MapRequest]>,
+}
+
+impl<S, S2> HandlerService<S> for BindState<S2>
+where
+    S2: Clone,
+{
+    fn call(&self, mut req: Request, _: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        if let Some(format) = self.format {
+            req.extensions_mut().insert(format);
+        }
+        for f in self.map_request.iter() {
+            f(&mut req);
         }
+        self.inner.call(req, self.state.clone())
+    }
+}
+
+/// A nested router, dispatched as a single route.
+struct Nested<S> {
+    router: Arc<NextDoor<S>>,
+}
+
+impl<S, S2> HandlerService<S> for Nested<S2>
+where
+    S2: Clone + Send + Sync + 'static,
+{
+    fn call(&self, req: Request, _: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        let router = self.router.clone();
+        Box::pin(async move {
+            let mut req = req;
+            router.prepare(&mut req);
+            router.route_request(req, router.state.clone(), false).await
+        })
+    }
+}
+
+/// A nested router whose state is derived from the parent router's state.
+struct NestedWithState<S, S2> {
+    router: Arc<NextDoor<S2>>,
+    state: Arc<dyn Fn(&S) -> S2 + Send + Sync>,
+}
+
+impl<S, S2> HandlerService<S> for NestedWithState<S, S2>
+where
+    S2: Clone + Send + Sync + 'static,
+{
+    fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        let router = self.router.clone();
+        let state = (self.state)(&state);
+        Box::pin(async move {
+            let mut req = req;
+            router.prepare(&mut req);
+            router.route_request(req, state, false).await
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use extract::State;
+    use extract::{Extension, State};
     use tokio_tungstenite::tungstenite::Message;
 
     #[tokio::test]
@@ -275,7 +882,7 @@ mod tests {
         let request = Request::from_ws_message(Message::Text(test_message.to_string()));
 
         let response = router.handler(request).await;
-        assert_eq!(response.status, Status::NotFountPath);
+        assert_eq!(response.status, Status::NotFoundPath);
     }
 
     #[tokio::test]
@@ -293,4 +900,284 @@ mod tests {
         assert_eq!(response.status, Status::OK);
         assert_eq!(response.body, format!("TestState - {}", test_message));
     }
+
+    #[tokio::test]
+    async fn test_discriminated_handler() {
+        let mut router = NextDoor::new();
+        router
+            .on("type", "chat.message", || async { "chat" })
+            .on("type", "chat.join", || async { "join" })
+            .on("/data/event", "trade", || async { "trade" })
+            .on("seq", "1", || async { "first" })
+            .text(|| async { "fallback" });
+
+        let cases = [
+            (r#"{"type":"chat.message","text":"hi"}"#, "chat"),
+            (r#"{"type":"chat.join"}"#, "join"),
+            (r#"{"data":{"event":"trade"}}"#, "trade"),
+            (r#"{"seq":1}"#, "first"),
+            (r#"{"type":"unknown"}"#, "fallback"),
+            ("not json", "fallback"),
+        ];
+
+        for (message, expected) in cases {
+            let request = Request::from_ws_message(Message::Text(message.to_string()));
+            let response = router.handler(request).await;
+            assert_eq!(response.status, Status::OK);
+            assert_eq!(response.body, expected, "message: {}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_state() {
+        let mut chat = NextDoor::with_state("chat");
+        chat.on(
+            "type",
+            "chat",
+            |State(state): State<&'static str>| async move { state },
+        );
+
+        let mut router = NextDoor::with_state(7);
+        router
+            .text(|State(state): State<i32>| async move { state.to_string() })
+            .merge(chat);
+
+        let request = Request::from_ws_message(Message::Text(r#"{"type":"chat"}"#.to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "chat");
+
+        let request = Request::from_ws_message(Message::Text("plain".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "7");
+    }
+
+    #[tokio::test]
+    async fn test_nest_router() {
+        let mut orders = NextDoor::with_state(Arc::new("orders".to_string()));
+        orders
+            .on(
+                "action",
+                "create",
+                |State(state): State<Arc<String>>| async move { format!("{} create", state) },
+            )
+            .text(|| async { "orders default" });
+
+        let mut router = NextDoor::new();
+        router
+            .nest("channel", "orders", orders)
+            .text(|| async { "top level" });
+
+        let cases = [
+            (r#"{"channel":"orders","action":"create"}"#, "orders create"),
+            (
+                r#"{"channel":"orders","action":"cancel"}"#,
+                "orders default",
+            ),
+            (r#"{"channel":"book"}"#, "top level"),
+        ];
+
+        for (message, expected) in cases {
+            let request = Request::from_ws_message(Message::Text(message.to_string()));
+            let response = router.handler(request).await;
+            assert_eq!(response.status, Status::OK);
+            assert_eq!(response.body, expected, "message: {}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merged_map_request_is_scoped() {
+        let mut chat = NextDoor::new();
+        chat.map_request(|req| {
+            req.extensions_mut().insert(1u64);
+        })
+        .on("type", "chat", |Extension(id): Extension<u64>| async move {
+            id.to_string()
+        });
+
+        let mut router = NextDoor::new();
+        router
+            .text(|req: Request| async move {
+                format!("mapped: {}", req.extensions().get::<u64>().is_some())
+            })
+            .merge(chat);
+
+        let request = Request::from_ws_message(Message::Text(r#"{"type":"chat"}"#.to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "1");
+
+        let request = Request::from_ws_message(Message::Text("plain".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "mapped: false");
+    }
+
+    #[tokio::test]
+    async fn test_nest_with_state() {
+        let mut orders = NextDoor::with_state(0);
+        orders.on("action", "create", |State(state): State<i32>| async move {
+            state.to_string()
+        });
+
+        let mut router = NextDoor::with_state(7);
+        router.nest_with_state("channel", "orders", orders, |state: &i32| state * 2);
+
+        let request = Request::from_ws_message(Message::Text(
+            r#"{"channel":"orders","action":"create"}"#.to_string(),
+        ));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "14");
+    }
+
+    #[tokio::test]
+    async fn test_handler_response_is_final() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+
+        let mut router = NextDoor::new();
+        router
+            .text(|extract::Json(_): extract::Json<Value>| async { "json" })
+            .text(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+            })
+            .text(|| async { "unreachable" });
+
+        let request = Request::from_ws_message(Message::Text("not json".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.status, Status::NoContent);
+        assert!(!response.is_rejection());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let request = Request::from_ws_message(Message::Text("{}".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "json");
+    }
+
+    #[tokio::test]
+    async fn test_all_routes_rejected() {
+        let mut router = NextDoor::new();
+        router.text(|extract::Json(_): extract::Json<Value>| async { "json" });
+
+        let request = Request::from_ws_message(Message::Text("not json".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.status, Status::JsonError);
+        assert!(response.is_rejection());
+
+        router.fallback(|req: Request| async move { format!("fallback {:?}", req.path) });
+
+        let request = Request::from_ws_message(Message::Text("not json".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "fallback Text");
+
+        let request = Request::from_ws_message(Message::Binary(vec![0xff]));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "fallback Binary");
+    }
+
+    #[tokio::test]
+    async fn test_nested_rejection_falls_through() {
+        let mut orders = NextDoor::new();
+        orders.on("action", "create", || async { "create" });
+
+        let mut router = NextDoor::new();
+        router
+            .nest("channel", "orders", orders)
+            .on("channel", "orders", || async { "parent" });
+
+        let request = Request::from_ws_message(Message::Text(
+            r#"{"channel":"orders","action":"cancel"}"#.to_string(),
+        ));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "parent");
+    }
+
+    #[tokio::test]
+    async fn test_nested_fallback_and_dead_letter_do_not_run() {
+        let letters = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let sink = letters.clone();
+
+        let mut orders = NextDoor::new();
+        orders
+            .on("action", "create", || async { "create" })
+            .fallback(|| async { "orders fallback" })
+            .dead_letter(move |_| {
+                sink.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            });
+
+        let mut router = NextDoor::new();
+        router
+            .nest("channel", "orders", orders)
+            .fallback(|| async { "top level fallback" });
+
+        let request = Request::from_ws_message(Message::Text(
+            r#"{"channel":"orders","action":"cancel"}"#.to_string(),
+        ));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "top level fallback");
+        assert_eq!(letters.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_dispatch() {
+        let mut router = NextDoor::new();
+        router
+            .text(|| async { "first" })
+            .text(|extract::Json(_): extract::Json<Value>| async { "json" })
+            .text(|| async { "second" })
+            .fan_out();
+
+        let request = Request::from_ws_message(Message::Text("plain".to_string()));
+        let bodies: Vec<_> = router
+            .dispatch(request)
+            .await
+            .into_iter()
+            .map(|response| response.body)
+            .collect();
+        assert_eq!(bodies, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let letters = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = letters.clone();
+
+        let mut router = NextDoor::new();
+        router
+            .text(|extract::Json(_): extract::Json<Value>| async { "json" })
+            .text(|Extension(id): Extension<u64>| async move { id.to_string() })
+            .dead_letter(move |letter| sink.lock().unwrap().push(letter));
+
+        let request = Request::from_ws_message(Message::Text("not json".to_string()));
+        assert!(router.handler(request).await.is_rejection());
+
+        let request = Request::from_ws_message(Message::Binary(vec![0xff, 0xfe]));
+        assert!(router.handler(request).await.is_rejection());
+
+        let letters = letters.lock().unwrap();
+        assert_eq!(letters.len(), 2);
+
+        let statuses: Vec<_> = letters[0].rejections.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [Status::JsonError, Status::InternalError]);
+        assert_eq!(letters[0].request.try_to_string().unwrap(), "not json");
+
+        assert_eq!(letters[1].request.path, Frames::Binary);
+        assert!(letters[1].rejections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_sees_rejections() {
+        let mut router = NextDoor::new();
+        router
+            .text(|extract::Json(_): extract::Json<Value>| async { "json" })
+            .fallback(
+                |extract::Rejections(rejections): extract::Rejections| async move {
+                    format!("{:?}", rejections[0].status)
+                },
+            );
+
+        let request = Request::from_ws_message(Message::Text("not json".to_string()));
+        let response = router.handler(request).await;
+        assert_eq!(response.body, "JsonError");
+    }
 }
@@ -0,0 +1,305 @@
+use std::{
+    any::Any,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tracing::error;
+
+use crate::{
+    codec::Format,
+    extract::Extension,
+    handler::{BoxHandlerService, HandlerService},
+    request::Request,
+    response::{IntoResponse, Response, Status},
+};
+
+/// Wraps a route with cross-cutting logic.
+///
+/// Applied to every route with [`NextDoor::layer`](crate::NextDoor::layer)
+/// or to a single handler with [`Handler::layer`](crate::handler::Handler::layer).
+pub trait Layer<S>: Send + Sync + 'static {
+    fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S>;
+}
+
+/// The rest of the route, handed to a [`from_fn`] middleware.
+pub struct Next<S> {
+    inner: BoxHandlerService<S>,
+    state: S,
+}
+
+impl<S> Next<S> {
+    pub fn run(self, req: Request) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        self.inner.call(req, self.state)
+    }
+}
+
+/// Create a layer from an async function.
+///
+/// ```ignore
+/// async fn timing(req: Request, next: Next<Arc<()>>) -> Response {
+///     let start = std::time::Instant::now();
+///     let response = next.run(req).await;
+///     tracing::info!(elapsed = ?start.elapsed(), "handled");
+///     response
+/// }
+///
+/// router.layer(from_fn(timing));
+/// ```
+pub fn from_fn<F>(f: F) -> FromFnLayer<F> {
+    FromFnLayer { f }
+}
+
+#[derive(Clone)]
+pub struct FromFnLayer<F> {
+    f: F,
+}
+
+impl<F, Fut, S, Res> Layer<S> for FromFnLayer<F>
+where
+    F: Fn(Request, Next<S>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Res> + Send + 'static,
+    Res: IntoResponse,
+    S: Clone + Send + Sync + 'static,
+{
+    fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S> {
+        Arc::new(FromFn {
+            f: self.f.clone(),
+            inner,
+        })
+    }
+}
+
+struct FromFn<F, S> {
+    f: F,
+    inner: BoxHandlerService<S>,
+}
+
+impl<F, Fut, S, Res> HandlerService<S> for FromFn<F, S>
+where
+    F: Fn(Request, Next<S>) -> Fut + Send + Sync,
+    Fut: Future<Output = Res> + Send + 'static,
+    Res: IntoResponse,
+{
+    fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        let next = Next {
+            inner: self.inner.clone(),
+            state,
+        };
+        let format = Format::of(&req);
+        let fut = (self.f)(req, next);
+        Box::pin(async move { fut.await.into_response().finalize(format) })
+    }
+}
+
+impl<S, T> Layer<S> for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+    S: 'static,
+{
+    fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S> {
+        Arc::new(AddExtension {
+            value: self.0.clone(),
+            inner,
+        })
+    }
+}
+
+struct AddExtension<T, S> {
+    value: T,
+    inner: BoxHandlerService<S>,
+}
+
+impl<T, S> HandlerService<S> for AddExtension<T, S>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn call(&self, mut req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        req.extensions_mut().insert(self.value.clone());
+        self.inner.call(req, state)
+    }
+}
+
+/// Turn panics in extractors and handlers into a [`Status::InternalError`] response.
+pub fn catch_panic() -> CatchPanicLayer {
+    CatchPanicLayer
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CatchPanicLayer;
+
+impl<S> Layer<S> for CatchPanicLayer
+where
+    S: Send + 'static,
+{
+    fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S> {
+        Arc::new(CatchPanic { inner })
+    }
+}
+
+struct CatchPanic<S> {
+    inner: BoxHandlerService<S>,
+}
+
+impl<S> HandlerService<S> for CatchPanic<S> {
+    fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        match catch_unwind(AssertUnwindSafe(|| self.inner.call(req, state))) {
+            Ok(fut) => Box::pin(CatchUnwind { fut }),
+            Err(panic) => Box::pin(std::future::ready(panic_response(panic))),
+        }
+    }
+}
+
+struct CatchUnwind {
+    fut: Pin<Box<dyn Future<Output = Response> + Send>>,
+}
+
+impl Future for CatchUnwind {
+    type Output = Response;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match catch_unwind(AssertUnwindSafe(|| self.fut.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(panic) => Poll::Ready(panic_response(panic)),
+        }
+    }
+}
+
+fn panic_response(panic: Box<dyn Any + Send>) -> Response {
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Handler panicked".to_string()
+    };
+
+    error!(panic = %message, "Handler panicked");
+    Response::error(Status::InternalError, message)
+}
+
+#[cfg(feature = "tower")]
+pub use self::tower_compat::{Route, TowerLayer};
+
+#[cfg(feature = "tower")]
+mod tower_compat {
+    use std::{
+        convert::Infallible,
+        future::{poll_fn, Future},
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use crate::{
+        handler::{BoxHandlerService, HandlerService},
+        request::Request,
+        response::{Response, Status},
+    };
+
+    /// A route as a [`tower::Service`], the innermost service of a [`TowerLayer`].
+    pub struct Route<S> {
+        inner: BoxHandlerService<S>,
+        state: S,
+    }
+
+    impl<S: Clone> Clone for Route<S> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                state: self.state.clone(),
+            }
+        }
+    }
+
+    impl<S> tower::Service<Request> for Route<S>
+    where
+        S: Clone,
+    {
+        type Response = Response;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request) -> Self::Future {
+            let fut = self.inner.call(req, self.state.clone());
+            Box::pin(async move { Ok(fut.await) })
+        }
+    }
+
+    /// Adapts a [`tower::Layer`] so it can wrap NextDoor routes.
+    ///
+    /// The tower service is built on every call around the state the route is called with, so
+    /// state a layer creates in [`tower::Layer::layer`] is not shared between messages.
+    pub struct TowerLayer<L> {
+        layer: Arc<L>,
+    }
+
+    impl<L> TowerLayer<L> {
+        pub fn new(layer: L) -> Self {
+            Self {
+                layer: Arc::new(layer),
+            }
+        }
+    }
+
+    impl<L> Clone for TowerLayer<L> {
+        fn clone(&self) -> Self {
+            Self {
+                layer: self.layer.clone(),
+            }
+        }
+    }
+
+    impl<L, S> super::Layer<S> for TowerLayer<L>
+    where
+        L: tower::Layer<Route<S>> + Send + Sync + 'static,
+        L::Service: tower::Service<Request, Response = Response> + Clone + Send + Sync + 'static,
+        <L::Service as tower::Service<Request>>::Error: Into<tower::BoxError>,
+        <L::Service as tower::Service<Request>>::Future: Send,
+        S: Clone + Send + Sync + 'static,
+    {
+        fn layer(&self, inner: BoxHandlerService<S>) -> BoxHandlerService<S> {
+            Arc::new(TowerService {
+                layer: self.layer.clone(),
+                inner,
+            })
+        }
+    }
+
+    struct TowerService<L, S> {
+        layer: Arc<L>,
+        inner: BoxHandlerService<S>,
+    }
+
+    impl<L, S> HandlerService<S> for TowerService<L, S>
+    where
+        L: tower::Layer<Route<S>>,
+        L::Service: tower::Service<Request, Response = Response> + Send + 'static,
+        <L::Service as tower::Service<Request>>::Error: Into<tower::BoxError>,
+        <L::Service as tower::Service<Request>>::Future: Send,
+    {
+        fn call(&self, req: Request, state: S) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+            let mut service = self.layer.layer(Route {
+                inner: self.inner.clone(),
+                state,
+            });
+            Box::pin(async move {
+                if let Err(err) = poll_fn(|cx| tower::Service::poll_ready(&mut service, cx)).await {
+                    return Response::error(Status::InternalError, err.into().to_string());
+                }
+
+                match tower::Service::call(&mut service, req).await {
+                    Ok(response) => response,
+                    Err(err) => Response::error(Status::InternalError, err.into().to_string()),
+                }
+            })
+        }
+    }
+}
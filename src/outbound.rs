@@ -0,0 +1,301 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+#[cfg(feature = "client")]
+use std::sync::atomic::AtomicBool;
+
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Most messages the priority lane holds. The lane ignores the [`OverflowPolicy`]: sending waits
+/// for room, or fails with `SendError::Full`.
+pub(crate) const PRIORITY_CAPACITY: usize = 64;
+
+/// What happens to a message sent while the outbound queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for room; `try_send` fails with `SendError::Full`.
+    #[default]
+    Block,
+    /// Drop the message being sent.
+    #[cfg(feature = "client")]
+    DropNewest,
+    /// Drop the oldest queued message to make room.
+    #[cfg(feature = "client")]
+    DropOldest,
+    /// Drop the message being sent and close the connection, so the client reconnects.
+    #[cfg(feature = "client")]
+    Disconnect,
+}
+
+/// A snapshot of the outbound queue.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    /// Messages waiting to be written, priority messages included.
+    pub depth: usize,
+    /// Priority messages waiting to be written.
+    pub priority_depth: usize,
+    pub capacity: usize,
+    /// Messages dropped by the overflow policy.
+    pub dropped: u64,
+    /// Messages written to the socket.
+    pub sent: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PushError {
+    Closed,
+    Full,
+}
+
+#[derive(Debug)]
+struct Queue {
+    priority: VecDeque<Message>,
+    normal: VecDeque<Message>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    closed: bool,
+}
+
+/// The queue between handlers and the socket writer.
+///
+/// Ping and pong frames, and messages sent with priority, go to a separate lane of
+/// [`PRIORITY_CAPACITY`] that is written first. Close frames stay behind the queued messages and
+/// are never dropped.
+#[derive(Debug)]
+pub(crate) struct Outbound {
+    queue: Mutex<Queue>,
+    readable: Notify,
+    writable: Notify,
+    #[cfg(feature = "client")]
+    overflowed: AtomicBool,
+    #[cfg(feature = "client")]
+    overflow: Notify,
+    #[cfg(feature = "client")]
+    dropped: AtomicU64,
+    #[cfg(feature = "client")]
+    connected: AtomicBool,
+    sent: AtomicU64,
+}
+
+impl Outbound {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            queue: Mutex::new(Queue {
+                priority: VecDeque::new(),
+                normal: VecDeque::new(),
+                capacity: capacity.max(1),
+                policy,
+                closed: false,
+            }),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            #[cfg(feature = "client")]
+            overflowed: AtomicBool::new(false),
+            #[cfg(feature = "client")]
+            overflow: Notify::new(),
+            #[cfg(feature = "client")]
+            dropped: AtomicU64::new(0),
+            #[cfg(feature = "client")]
+            connected: AtomicBool::new(false),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// An empty queue with the same capacity and policy.
+    #[cfg(feature = "client")]
+    pub(crate) fn empty_like(&self) -> Self {
+        let queue = self.queue.lock().unwrap();
+        Self::new(queue.capacity, queue.policy)
+    }
+
+    /// Change the capacity of the normal lane. Messages already queued past a smaller capacity
+    /// are kept.
+    #[cfg(feature = "client")]
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        self.queue.lock().unwrap().capacity = capacity.max(1);
+        self.writable.notify_waiters();
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        self.queue.lock().unwrap().policy = policy;
+        self.writable.notify_waiters();
+    }
+
+    /// Queue `message`, waiting for room with [`OverflowPolicy::Block`].
+    pub(crate) async fn send(&self, message: Message, priority: bool) -> Result<(), PushError> {
+        let mut message = Some(message);
+        loop {
+            let writable = self.writable.notified();
+            tokio::pin!(writable);
+            writable.as_mut().enable();
+
+            match self.push(&mut message, priority) {
+                Err(PushError::Full) => writable.await,
+                result => return result,
+            }
+        }
+    }
+
+    /// Queue `message` without waiting, failing with [`PushError::Full`] with
+    /// [`OverflowPolicy::Block`].
+    #[cfg(feature = "client")]
+    pub(crate) fn try_send(&self, message: Message, priority: bool) -> Result<(), PushError> {
+        self.push(&mut Some(message), priority)
+    }
+
+    /// Takes the message out of `message` unless the queue is full and the policy blocks.
+    fn push(&self, message: &mut Option<Message>, priority: bool) -> Result<(), PushError> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return Err(PushError::Closed);
+        }
+
+        let priority = priority || matches!(message, Some(Message::Ping(_) | Message::Pong(_)));
+        if priority {
+            if queue.priority.len() >= PRIORITY_CAPACITY {
+                return Err(PushError::Full);
+            }
+            queue.priority.extend(message.take());
+        } else if queue.normal.len() < queue.capacity || matches!(message, Some(Message::Close(_)))
+        {
+            queue.normal.extend(message.take());
+        } else {
+            match queue.policy {
+                OverflowPolicy::Block => return Err(PushError::Full),
+                #[cfg(feature = "client")]
+                OverflowPolicy::DropNewest => {
+                    message.take();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Outbound queue full, dropped newest message");
+                    return Ok(());
+                }
+                #[cfg(feature = "client")]
+                OverflowPolicy::DropOldest => {
+                    let oldest = queue
+                        .normal
+                        .iter()
+                        .position(|queued| !matches!(queued, Message::Close(_)));
+                    match oldest {
+                        Some(index) => {
+                            queue.normal.remove(index);
+                            queue.normal.extend(message.take());
+                        }
+                        // Only close frames are queued, and those are never dropped.
+                        None => drop(message.take()),
+                    }
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Outbound queue full, dropped oldest message");
+                }
+                #[cfg(feature = "client")]
+                OverflowPolicy::Disconnect => {
+                    message.take();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    self.overflowed.store(true, Ordering::Relaxed);
+                    self.overflow.notify_waiters();
+                    return Ok(());
+                }
+            }
+        }
+        drop(queue);
+
+        self.readable.notify_one();
+        Ok(())
+    }
+
+    /// The next message to write, or `None` once the queue is closed and empty.
+    pub(crate) async fn recv(&self) -> Option<Message> {
+        loop {
+            let readable = self.readable.notified();
+            tokio::pin!(readable);
+            readable.as_mut().enable();
+
+            if let Some(message) = self.try_recv() {
+                return Some(message);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            readable.await;
+        }
+    }
+
+    /// The next message to write, priority messages first.
+    pub(crate) fn try_recv(&self) -> Option<Message> {
+        let mut queue = self.queue.lock().unwrap();
+        let message = match queue.priority.pop_front() {
+            Some(message) => message,
+            None => queue.normal.pop_front()?,
+        };
+        drop(queue);
+
+        self.writable.notify_waiters();
+        Some(message)
+    }
+
+    pub(crate) fn record_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stop accepting messages; the writer finishes once the queued ones are written.
+    pub(crate) fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.readable.notify_one();
+        self.writable.notify_waiters();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.queue.lock().unwrap().closed
+    }
+
+    /// Whether a connection is writing the queue.
+    #[cfg(feature = "client")]
+    pub(crate) fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    /// Resolves when a message is dropped with [`OverflowPolicy::Disconnect`].
+    #[cfg(feature = "client")]
+    pub(crate) async fn overflowed(&self) {
+        loop {
+            let overflow = self.overflow.notified();
+            tokio::pin!(overflow);
+            overflow.as_mut().enable();
+
+            if self.overflowed.swap(false, Ordering::Relaxed) {
+                return;
+            }
+            overflow.await;
+        }
+    }
+
+    /// Forget an overflow that happened while no connection was running.
+    #[cfg(feature = "client")]
+    pub(crate) fn reset_overflow(&self) {
+        self.overflowed.store(false, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn metrics(&self) -> QueueMetrics {
+        let queue = self.queue.lock().unwrap();
+        QueueMetrics {
+            depth: queue.priority.len() + queue.normal.len(),
+            priority_depth: queue.priority.len(),
+            capacity: queue.capacity,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+        }
+    }
+}
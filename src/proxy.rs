@@ -0,0 +1,397 @@
+use std::{
+    env, fmt,
+    io::{self, ErrorKind},
+    net::IpAddr,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream},
+};
+use tokio_tungstenite::tungstenite::http::Uri;
+
+/// A proxy the [`Client`](crate::Client) tunnels its TCP stream through before the WebSocket
+/// handshake, see [`Client::with_proxy`](crate::Client::with_proxy).
+///
+/// ```ignore
+/// let proxy = Proxy::new("http://proxy.corp:3128")?
+///     .basic_auth("user", "password")
+///     .no_proxy(["localhost", ".internal"]);
+/// ```
+#[derive(Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    auth: Option<(String, String)>,
+    no_proxy: Vec<NoProxy>,
+}
+
+/// A `NO_PROXY` entry, matching every port unless it names one.
+#[derive(Debug, Clone)]
+struct NoProxy {
+    host: String,
+    port: Option<u16>,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.auth.as_ref().map(|(user, _)| user))
+            .field("no_proxy", &self.no_proxy)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    /// HTTP `CONNECT` tunnel.
+    Http,
+    /// `socks5h://` has the proxy resolve host names, `socks5://` resolves them locally.
+    Socks5 { remote_dns: bool },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("Invalid proxy URL: {0}")]
+    InvalidUrl(String),
+    #[error("Failed to reach proxy: {0}")]
+    Io(#[from] io::Error),
+    #[error("Proxy refused the tunnel: {0}")]
+    Refused(String),
+    #[error("Proxy authentication failed")]
+    Auth,
+    #[error("{0} is longer than SOCKS5 allows")]
+    TooLong(&'static str),
+}
+
+impl Proxy {
+    /// Parse a proxy URL, `http://`, `socks5://` or `socks5h://`, with optional credentials.
+    ///
+    /// Credentials are percent-decoded. The port defaults to 1080 for SOCKS5 and 80 for HTTP.
+    pub fn new(url: &str) -> Result<Self, ProxyError> {
+        let invalid = || ProxyError::InvalidUrl(url.to_string());
+
+        let uri: Uri = url.parse().map_err(|_| invalid())?;
+        let (kind, default_port) = match uri.scheme_str() {
+            Some("http") => (ProxyKind::Http, 80),
+            Some("socks5") => (ProxyKind::Socks5 { remote_dns: false }, 1080),
+            Some("socks5h") => (ProxyKind::Socks5 { remote_dns: true }, 1080),
+            _ => return Err(invalid()),
+        };
+        let authority = uri.authority().ok_or_else(invalid)?;
+
+        let auth = match authority
+            .as_str()
+            .rsplit_once('@')
+            .and_then(|(userinfo, _)| userinfo.split_once(':'))
+        {
+            Some((user, password)) => Some((
+                percent_decode(user).ok_or_else(invalid)?,
+                percent_decode(password).ok_or_else(invalid)?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            kind,
+            // IPv6 literals keep their brackets in the authority, not in a socket address.
+            host: authority
+                .host()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port: authority.port_u16().unwrap_or(default_port),
+            auth,
+            no_proxy: Vec::new(),
+        })
+    }
+
+    /// The proxy from the environment for a `ws` or `wss` target.
+    ///
+    /// `wss` uses `HTTPS_PROXY`, `ws` uses `HTTP_PROXY`, and both fall back to `ALL_PROXY`;
+    /// lowercase names are accepted too. A value without a scheme, such as `proxy.corp:3128`,
+    /// is an HTTP proxy. `NO_PROXY` is applied.
+    pub fn from_env(scheme: &str) -> Option<Self> {
+        let names: &[&str] = match scheme {
+            "wss" => &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"],
+            _ => &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"],
+        };
+        let mut url = names
+            .iter()
+            .find_map(|name| env::var(name).ok().filter(|url| !url.is_empty()))?;
+        if !url.contains("://") {
+            url = format!("http://{}", url);
+        }
+        let no_proxy = env::var("NO_PROXY")
+            .or_else(|_| env::var("no_proxy"))
+            .unwrap_or_default();
+
+        match Self::new(&url) {
+            Ok(proxy) => Some(proxy.no_proxy(no_proxy.split(','))),
+            Err(err) => {
+                tracing::warn!(error = %err, "Ignoring proxy from the environment");
+                None
+            }
+        }
+    }
+
+    /// Credentials sent as `Proxy-Authorization: Basic`, or as SOCKS5 username/password.
+    pub fn basic_auth<U: Into<String>, P: Into<String>>(mut self, user: U, password: P) -> Self {
+        self.auth = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Hosts connected to directly, in `NO_PROXY` syntax: `*` for every host, and
+    /// `example.com` or `.example.com` for the domain and its subdomains. An entry with a port,
+    /// such as `example.com:8080` or `[::1]:8080`, only matches that port.
+    pub fn no_proxy<I, H>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = H>,
+        H: AsRef<str>,
+    {
+        self.no_proxy.extend(hosts.into_iter().filter_map(|entry| {
+            let entry = entry.as_ref().trim().to_lowercase();
+            let (host, port) = split_port(&entry);
+            let host = host.trim_start_matches('.');
+            (!host.is_empty()).then(|| NoProxy {
+                host: host.to_string(),
+                port,
+            })
+        }));
+        self
+    }
+
+    pub(crate) fn bypasses(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.no_proxy.iter().any(|entry| {
+            if entry.port.is_some_and(|entry_port| entry_port != port) {
+                return false;
+            }
+            entry.host == "*"
+                || host.eq_ignore_ascii_case(&entry.host)
+                || host
+                    .to_lowercase()
+                    .strip_suffix(entry.host.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    /// Connect to the proxy and open a tunnel to `host:port`.
+    pub(crate) async fn tunnel(&self, host: &str, port: u16) -> Result<TcpStream, ProxyError> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        match self.kind {
+            ProxyKind::Http => self.http_connect(&mut stream, host, port).await?,
+            ProxyKind::Socks5 { remote_dns } => {
+                self.socks5_connect(&mut stream, host, port, remote_dns)
+                    .await?
+            }
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), ProxyError> {
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some((user, password)) = &self.auth {
+            let credentials = base64(format!("{}:{}", user, password).as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte so nothing past the response head is consumed.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() > 8192 {
+                return Err(ProxyError::Refused("response head too large".to_string()));
+            }
+            head.push(stream.read_u8().await?);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let status = head.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some("200") => Ok(()),
+            Some("407") => Err(ProxyError::Auth),
+            _ => Err(ProxyError::Refused(status.to_string())),
+        }
+    }
+
+    async fn socks5_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+        remote_dns: bool,
+    ) -> Result<(), ProxyError> {
+        const NO_AUTH: u8 = 0x00;
+        const USER_PASSWORD: u8 = 0x02;
+
+        let greeting: &[u8] = match self.auth {
+            Some(_) => &[5, 2, NO_AUTH, USER_PASSWORD],
+            None => &[5, 1, NO_AUTH],
+        };
+        stream.write_all(greeting).await?;
+
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        match (choice, &self.auth) {
+            ([5, NO_AUTH], _) => {}
+            ([5, USER_PASSWORD], Some((user, password))) => {
+                let mut request = vec![1];
+                push_field(&mut request, user, "SOCKS5 username")?;
+                push_field(&mut request, password, "SOCKS5 password")?;
+                stream.write_all(&request).await?;
+
+                let mut status = [0; 2];
+                stream.read_exact(&mut status).await?;
+                if status[1] != 0 {
+                    return Err(ProxyError::Auth);
+                }
+            }
+            _ => return Err(ProxyError::Auth),
+        }
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if !remote_dns => {
+                let address = lookup_host((host, port)).await?.next().ok_or_else(|| {
+                    io::Error::new(ErrorKind::NotFound, format!("{} did not resolve", host))
+                })?;
+                Some(address.ip())
+            }
+            Err(_) => None,
+        };
+
+        let mut request = vec![5, 1, 0];
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {
+                request.push(3);
+                push_field(&mut request, host, "SOCKS5 host name")?;
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(ProxyError::Refused(format!("SOCKS5 reply {}", reply[1])));
+        }
+        let bound = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => stream.read_u8().await? as usize,
+            atyp => {
+                return Err(ProxyError::Io(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown SOCKS5 address type {}", atyp),
+                )))
+            }
+        };
+        let mut address = vec![0; bound + 2];
+        stream.read_exact(&mut address).await?;
+        Ok(())
+    }
+}
+
+/// Where the [`Client`](crate::Client) gets its proxy from.
+#[derive(Debug, Clone)]
+pub(crate) enum ProxyConfig {
+    Fixed(Proxy),
+    Env,
+}
+
+impl ProxyConfig {
+    /// The proxy to use for `host:port`, if any.
+    pub(crate) fn resolve(&self, scheme: &str, host: &str, port: u16) -> Option<Proxy> {
+        let proxy = match self {
+            Self::Fixed(proxy) => proxy.clone(),
+            Self::Env => Proxy::from_env(scheme)?,
+        };
+        (!proxy.bypasses(host, port)).then_some(proxy)
+    }
+}
+
+/// Append a SOCKS5 length-prefixed field.
+fn push_field(request: &mut Vec<u8>, value: &str, name: &'static str) -> Result<(), ProxyError> {
+    let len = u8::try_from(value.len()).map_err(|_| ProxyError::TooLong(name))?;
+    request.push(len);
+    request.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Split a trailing port off a `NO_PROXY` entry; IPv6 addresses need brackets to take one.
+fn split_port(entry: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = entry.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, port)) => (host, port.strip_prefix(':').and_then(|p| p.parse().ok())),
+            None => (entry, None),
+        };
+    }
+    match entry.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (entry, None),
+        },
+        _ => (entry, None),
+    }
+}
+
+/// Decode `%XX` escapes, `None` if an escape is malformed or the result is not UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+/// Decides whether and when a [`Client`](crate::Client) reconnects.
+///
+/// Used both when connecting fails and when an established connection drops.
+pub trait ReconnectPolicy: Send + Sync + 'static {
+    /// Delay before reconnect attempt `attempt`, counting from 1, or `None` to give up.
+    fn next_delay(&self, attempt: u32) -> Option<Duration>;
+
+    /// How long a connection has to stay up before the attempt count starts over.
+    fn stable_after(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
+/// Jittered exponential backoff. Delays are in milliseconds.
+///
+/// Attempt `n` waits `initial_delay * backoff_factor^(n - 1)`, capped at `max_delay`, then
+/// randomly moved by up to `jitter` of itself in either direction.
+///
+/// ```ignore
+/// let config = ReconnectConfig::default()
+///     .with_initial_delay(500)
+///     .with_max_retries(10);
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ReconnectConfig {
+    pub initial_delay: u64,
+    pub max_delay: u64,
+    pub max_retries: u32,
+    pub backoff_factor: f64,
+    pub jitter: f64,
+    pub reset_after: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            max_delay: 30000,
+            max_retries: 8,
+            backoff_factor: 1.5,
+            jitter: 0.2,
+            reset_after: 30000,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    pub fn with_initial_delay(mut self, ms: u64) -> Self {
+        self.initial_delay = ms;
+        self
+    }
+
+    pub fn with_max_delay(mut self, ms: u64) -> Self {
+        self.max_delay = ms;
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_backoff_factor(mut self, factor: f64) -> Self {
+        self.backoff_factor = factor;
+        self
+    }
+
+    /// Fraction of the delay, between 0 and 1, it is randomly moved by.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// How long, in milliseconds, a connection has to stay up before the attempt count starts
+    /// over.
+    pub fn with_reset_after(mut self, ms: u64) -> Self {
+        self.reset_after = ms;
+        self
+    }
+
+    /// Delay before attempt `attempt`, ignoring `max_retries`.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_delay as f64 * self.backoff_factor.powi(exponent))
+            .min(self.max_delay as f64);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * random_unit() - 1.0);
+
+        Duration::from_millis((delay * (1.0 + jitter)) as u64)
+    }
+}
+
+impl ReconnectPolicy for ReconnectConfig {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.max_retries {
+            return None;
+        }
+        Some(self.backoff(attempt))
+    }
+
+    fn stable_after(&self) -> Duration {
+        Duration::from_millis(self.reset_after)
+    }
+}
+
+/// A random number in `[0, 1)`, good enough to spread reconnects apart but not for anything
+/// security related.
+///
+/// Numbers come from a process-wide SplitMix64 generator, seeded once from [`RandomState`],
+/// which the standard library seeds from the operating system.
+fn random_unit() -> f64 {
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+    static STATE: OnceLock<AtomicU64> = OnceLock::new();
+
+    let state = STATE.get_or_init(|| AtomicU64::new(RandomState::new().build_hasher().finish()));
+    let mut z = state
+        .fetch_add(GAMMA, Ordering::Relaxed)
+        .wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
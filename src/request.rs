@@ -1,8 +1,9 @@
-use std::string::FromUtf8Error;
+use std::{str::Utf8Error, string::FromUtf8Error, sync::Arc, time::SystemTime};
 
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use tokio_tungstenite::tungstenite::{
+    http::{Extensions, HeaderMap},
     protocol::{frame::coding::CloseCode, CloseFrame as TCloseFrame},
     Message,
 };
@@ -17,10 +18,14 @@ pub enum Frames {
 }
 
 /// Request of Nextdoor
+///
+/// Cloning is cheap: the body and the extensions are shared, and the extensions are copied
+/// only when a clone changes them.
 #[derive(Clone)]
 pub struct Request {
     pub path: Frames,
     body: Bytes,
+    extensions: Arc<Extensions>,
 }
 
 /// CloseFrame of Nextdoor
@@ -30,13 +35,51 @@ pub struct CloseFrame {
     pub code: u16,
 }
 
+/// The connection a request arrived on.
+///
+/// The client and the server insert it into the extensions of every request they dispatch,
+/// see [`Connection`](crate::extract::Connection).
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Unique for the lifetime of the process; a reconnect gets a new id.
+    pub id: u64,
+    /// The URL the client connected to, or the request URI on the server.
+    pub url: String,
+    /// Handshake response headers on the client, handshake request headers on the server.
+    pub headers: HeaderMap,
+    /// The subprotocol the server accepted, client only.
+    pub protocol: Option<String>,
+    pub connected_at: SystemTime,
+}
+
+impl ConnectionInfo {
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn new(url: String, headers: HeaderMap) -> Self {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            url,
+            headers,
+            protocol: None,
+            connected_at: SystemTime::now(),
+        }
+    }
+}
+
 impl Request {
     pub fn new(path: Frames, body: Bytes) -> Self {
-        Self { path, body }
+        Self {
+            path,
+            body,
+            extensions: Arc::default(),
+        }
     }
 
     pub fn from_ws_message(message: Message) -> Self {
-        let (frame_type, body) = match message.clone() {
+        let (frame_type, body) = match message {
             Message::Text(text) => (Frames::Text, Bytes::from(text)),
             Message::Binary(data) => (Frames::Binary, Bytes::from(data)),
             Message::Ping(data) => (Frames::Ping, Bytes::from(data)),
@@ -57,38 +100,28 @@ impl Request {
             Message::Frame(frame) => (Frames::Binary, Bytes::from(frame.into_data())),
         };
 
-        Self {
-            path: frame_type,
-            body,
-        }
+        Self::new(frame_type, body)
     }
 
     pub fn into_ws_message(self) -> Message {
-        match self.path {
-            Frames::Text => Message::Text(self.try_to_string().unwrap()),
-            Frames::Binary => Message::Binary(self.body.to_vec()),
-            Frames::Ping => Message::Ping(self.body.to_vec()),
-            Frames::Pong => Message::Pong(self.body.to_vec()),
-            Frames::Close => {
-                if self.body.is_empty() {
-                    Message::Close(None)
-                } else {
-                    let data: CloseFrame =
-                        serde_json::from_str(&self.try_to_string().unwrap()).unwrap();
-
-                    Message::Close(Some(TCloseFrame {
-                        reason: data.reason.into(),
-                        code: CloseCode::from(data.code),
-                    }))
-                }
-            }
-        }
+        to_ws_message(&self.path, self.body)
     }
 
+    /// Copy the body into a `String`, see [`as_str`](Self::as_str) for a borrowed view.
     pub fn try_to_string(&self) -> Result<String, FromUtf8Error> {
         String::from_utf8(self.body.to_vec())
     }
 
+    /// The body as `&str`, validated without copying.
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Copy the body into a `Vec`.
     pub fn to_vec(&self) -> Vec<u8> {
         self.body.to_vec()
     }
@@ -101,7 +134,55 @@ impl Request {
         self.body.is_empty()
     }
 
+    /// The body, sharing the buffer of the request.
     pub fn body(&self) -> Bytes {
         self.body.clone()
     }
+
+    pub fn into_body(self) -> Bytes {
+        self.body
+    }
+
+    /// Typed data attached to the request, such as the [`ConnectionInfo`].
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        Arc::make_mut(&mut self.extensions)
+    }
+
+    /// The same request with another body, sharing the extensions.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    pub(crate) fn with_body(&self, body: Bytes) -> Self {
+        Self {
+            path: self.path.clone(),
+            body,
+            extensions: self.extensions.clone(),
+        }
+    }
+}
+
+/// Build the tungstenite message for a frame kind and its body.
+///
+/// A close body is the JSON encoded [`CloseFrame`]; an empty body is a close without a frame. A
+/// body that is not a `CloseFrame` is logged and also sent as a close without a frame.
+pub(crate) fn to_ws_message(frame: &Frames, body: Bytes) -> Message {
+    match frame {
+        Frames::Text => Message::Text(String::from_utf8_lossy(&body).into_owned()),
+        Frames::Binary => Message::Binary(body.to_vec()),
+        Frames::Ping => Message::Ping(body.to_vec()),
+        Frames::Pong => Message::Pong(body.to_vec()),
+        Frames::Close if body.is_empty() => Message::Close(None),
+        Frames::Close => match serde_json::from_slice::<CloseFrame>(&body) {
+            Ok(data) => Message::Close(Some(TCloseFrame {
+                reason: data.reason.into(),
+                code: CloseCode::from(data.code),
+            })),
+            Err(e) => {
+                tracing::warn!(error = %e, "Invalid close frame body, closing without a frame");
+                Message::Close(None)
+            }
+        },
+    }
 }
@@ -1,8 +1,16 @@
+use std::{error::Error, fmt, string::FromUtf8Error, sync::Arc};
+
+use bytes::Bytes;
 use serde::Serialize;
+use tokio_tungstenite::tungstenite::Message;
 
-use crate::extract::Json;
+use crate::{
+    codec::Format,
+    extract::{Binary, Close, Json, Ping, Pong},
+    request::{to_ws_message, CloseFrame, Frames},
+};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Status {
     OK,
     NoContent,
@@ -12,13 +20,38 @@ pub enum Status {
     Reconnect,
 
     NotImplemented,
+    InternalError,
 
     JsonError,
-    NotFountPath,
+    /// A codec extractor could not decode the body.
+    DecodeError,
+    /// No route exists for the frame type.
+    NotFoundPath,
     FromStringError,
+
+    /// An application defined error code, reported as is by [`Status::code`].
+    Custom(u16),
 }
 
 impl Status {
+    #[deprecated(note = "renamed to `Status::NotFoundPath`")]
+    #[allow(non_upper_case_globals)]
+    pub const NotFountPath: Status = Status::NotFoundPath;
+
+    /// Numeric code of the status, following the HTTP status codes where one fits.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::OK => 200,
+            Self::NoContent => 204,
+            Self::Reconnect => 307,
+            Self::JsonError | Self::DecodeError | Self::FromStringError => 400,
+            Self::NotFound | Self::NotFoundPath => 404,
+            Self::InternalError => 500,
+            Self::NotImplemented => 501,
+            Self::Custom(code) => *code,
+        }
+    }
+
     pub fn is_success(&self) -> bool {
         *self == Status::OK
     }
@@ -32,16 +65,41 @@ impl Status {
     }
 }
 
+/// Response of Nextdoor
+///
+/// `frame` is the kind of websocket frame the body is sent as.
+#[derive(Debug)]
 pub struct Response {
     pub status: Status,
-    pub body: String,
+    pub frame: Frames,
+    pub body: Bytes,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+    rejected: bool,
+    deferred: Option<Deferred>,
+}
+
+/// Encoding of a [`Typed`](crate::codec::Typed) body, run once the router's format is known.
+struct Deferred(Box<dyn FnOnce(Format) -> Response + Send>);
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Deferred")
+    }
 }
 
 impl Response {
     pub fn new<I: Into<String>>(status: Status, body: I) -> Self {
+        Self::with_frame(status, Frames::Text, body.into())
+    }
+
+    pub fn with_frame<B: Into<Bytes>>(status: Status, frame: Frames, body: B) -> Self {
         Self {
             status,
+            frame,
             body: body.into(),
+            source: None,
+            rejected: false,
+            deferred: None,
         }
     }
 
@@ -52,6 +110,106 @@ impl Response {
     pub fn error<I: Into<String>>(status: Status, message: I) -> Self {
         Self::new(status, message)
     }
+
+    pub fn binary<B: Into<Bytes>>(body: B) -> Self {
+        Self::with_frame(Status::OK, Frames::Binary, body)
+    }
+
+    pub fn ping<B: Into<Bytes>>(body: B) -> Self {
+        Self::with_frame(Status::OK, Frames::Ping, body)
+    }
+
+    pub fn pong<B: Into<Bytes>>(body: B) -> Self {
+        Self::with_frame(Status::OK, Frames::Pong, body)
+    }
+
+    pub fn close(frame: Option<CloseFrame>) -> Self {
+        let body = match frame {
+            Some(frame) => match serde_json::to_vec(&frame) {
+                Ok(body) => body,
+                Err(err) => return Self::error(Status::JsonError, err.to_string()),
+            },
+            None => Vec::new(),
+        };
+
+        Self::with_frame(Status::OK, Frames::Close, body)
+    }
+
+    pub fn try_to_string(&self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.body.to_vec())
+    }
+
+    /// Keep the error that caused this response, for logging and error mapping.
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn source(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        self.source.as_deref()
+    }
+
+    pub(crate) fn shared_source(&self) -> Option<Arc<dyn Error + Send + Sync>> {
+        self.source.clone()
+    }
+
+    /// The `{"error":{"code":..,"status":..,"message":..}}` reply for an error response,
+    /// see [`NextDoor::map_error`](crate::NextDoor::map_error).
+    ///
+    /// `message` is the body of the response, or the source error if the body is empty.
+    pub fn to_json_error(&self) -> Response {
+        let message = match (self.body.is_empty(), self.source()) {
+            (true, Some(source)) => source.to_string(),
+            _ => String::from_utf8_lossy(&self.body).into_owned(),
+        };
+        let body = serde_json::json!({
+            "error": {
+                "code": self.status.code(),
+                "status": format!("{:?}", self.status),
+                "message": message,
+            }
+        });
+
+        Response::ok(body.to_string())
+    }
+
+    /// A response whose body is encoded by `encode` in the format of the router.
+    pub(crate) fn deferred<F>(encode: F) -> Self
+    where
+        F: FnOnce(Format) -> Response + Send + 'static,
+    {
+        let mut response = Self::new(Status::OK, "");
+        response.deferred = Some(Deferred(Box::new(encode)));
+        response
+    }
+
+    /// Run the deferred encoding, if any, in `format`.
+    pub(crate) fn finalize(mut self, format: Format) -> Self {
+        match self.deferred.take() {
+            Some(Deferred(encode)) => encode(format),
+            None => self,
+        }
+    }
+
+    /// Mark the response as a rejection: the route does not handle this message.
+    ///
+    /// Extractor rejections are marked automatically. The router tries the next route after a
+    /// rejection, while any other response is final.
+    pub fn into_rejection(mut self) -> Self {
+        self.rejected = true;
+        self
+    }
+
+    pub fn is_rejection(&self) -> bool {
+        self.rejected
+    }
+
+    pub fn into_ws_message(self) -> Message {
+        to_ws_message(&self.frame, self.body)
+    }
 }
 
 pub trait IntoResponse {
@@ -119,6 +277,42 @@ impl IntoResponse for Response {
     }
 }
 
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::binary(self)
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self) -> Response {
+        Response::binary(self)
+    }
+}
+
+impl IntoResponse for Binary {
+    fn into_response(self) -> Response {
+        Response::binary(self.0)
+    }
+}
+
+impl IntoResponse for Ping {
+    fn into_response(self) -> Response {
+        Response::ping(self.0)
+    }
+}
+
+impl IntoResponse for Pong {
+    fn into_response(self) -> Response {
+        Response::pong(self.0)
+    }
+}
+
+impl IntoResponse for Close {
+    fn into_response(self) -> Response {
+        Response::close(self.0)
+    }
+}
+
 impl<T> IntoResponse for Json<T>
 where
     T: Serialize,
@@ -0,0 +1,192 @@
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{Request as HandshakeRequest, Response as HandshakeResponse},
+        http::Extensions,
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    connection::{receive_messages, send_messages, shutdown, Activity, Disconnect, ExecutionMode},
+    extract::Connection,
+    outbound::{Outbound, OverflowPolicy},
+    request::ConnectionInfo,
+    NextDoor,
+};
+
+pub fn serve<S>(router: NextDoor<S>, listener: TcpListener) -> Server<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Server {
+        listener,
+        router: Arc::new(router),
+        capacity: 100,
+        close_timeout: Duration::from_secs(5),
+    }
+}
+
+pub struct Server<S> {
+    listener: TcpListener,
+    router: Arc<NextDoor<S>>,
+    capacity: usize,
+    close_timeout: Duration,
+}
+
+impl<S> Server<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Accept connections until ctrl-c, driving each one through the router on its own task.
+    pub async fn run(self) {
+        self.run_with_shutdown(shutdown()).await
+    }
+
+    /// Accept connections until `signal` completes, then close the open connections.
+    ///
+    /// Each connection flushes its queued replies and sends a going away close frame. The
+    /// server waits up to the close timeout for the peers to finish the close handshake and
+    /// drops the connections still open after that.
+    #[instrument(skip(self, signal), fields(addr = ?self.listener.local_addr().ok()))]
+    pub async fn run_with_shutdown<F>(self, signal: F)
+    where
+        F: Future<Output = ()> + Send,
+    {
+        tokio::pin!(signal);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                result = self.listener.accept() => {
+                    match result {
+                        Ok((stream, addr)) => {
+                            let router = self.router.clone();
+                            connections.spawn(serve_connection(
+                                stream,
+                                addr,
+                                router,
+                                self.capacity,
+                                shutdown_rx.clone(),
+                            ));
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Failed to accept TCP connection");
+                        }
+                    }
+                }
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                _ = &mut signal => {
+                    info!("Shutting down gracefully");
+                    break;
+                }
+            }
+        }
+
+        shutdown_tx.send_replace(true);
+        let closed = async { while connections.join_next().await.is_some() {} };
+        if timeout(self.close_timeout, closed).await.is_err() {
+            warn!(
+                open = connections.len(),
+                "Connections did not close in time, dropping them"
+            );
+            connections.shutdown().await;
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.capacity = capacity;
+
+        self
+    }
+
+    /// How long shutdown waits for the open connections to close, 5 seconds by default.
+    pub fn set_close_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.close_timeout = timeout;
+        self
+    }
+}
+
+#[instrument(skip(stream, router, capacity, shutdown))]
+async fn serve_connection<S>(
+    stream: TcpStream,
+    addr: SocketAddr,
+    router: Arc<NextDoor<S>>,
+    capacity: usize,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    S: Clone + Send + Sync + 'static,
+{
+    let mut handshake = None;
+    // The rejection type is fixed by tungstenite; this callback never rejects.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &HandshakeRequest, response: HandshakeResponse| {
+        handshake = Some((request.uri().to_string(), request.headers().clone()));
+        Ok(response)
+    };
+    let ws_stream = match accept_hdr_async(stream, callback).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            warn!(error = %e, "WebSocket handshake failed");
+            return;
+        }
+    };
+    let (url, headers) = handshake.unwrap_or_default();
+    let info = ConnectionInfo::new(url, headers);
+    debug!(id = info.id, url = %info.url, "WebSocket connection accepted");
+
+    let mut extensions = Extensions::new();
+    extensions.insert(Connection(Arc::new(info)));
+
+    let (write, read) = ws_stream.split();
+    let queue = Arc::new(Outbound::new(capacity, OverflowPolicy::Block));
+
+    let writer = queue.clone();
+    let close = async move {
+        if shutdown.wait_for(|shutdown| *shutdown).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        debug!("Closing WebSocket connection for shutdown");
+        Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "".into(),
+        }))
+    };
+    let send_task = tokio::spawn(async move { send_messages(write, &writer, close, None).await });
+    let disconnect = receive_messages(
+        read,
+        router,
+        queue.clone(),
+        Activity::new(),
+        extensions,
+        ExecutionMode::Sequential,
+    )
+    .await;
+    queue.close();
+
+    match send_task.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!(error = %e, "Failed writing to WebSocket connection"),
+        Err(e) => error!(error = %e, "Send task join error"),
+    }
+    match disconnect {
+        Disconnect::Reconnect(url) => {
+            debug!(?url, "Reconnect response ignored by server connection")
+        }
+        Disconnect::Closed(frame) => debug!(?frame, "WebSocket connection closed"),
+        Disconnect::Error(e) => debug!(error = %e, "WebSocket connection failed"),
+        #[cfg(feature = "client")]
+        Disconnect::Timeout | Disconnect::Overflow | Disconnect::Shutdown => {}
+    }
+}
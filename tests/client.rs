@@ -0,0 +1,1170 @@
+#![cfg(feature = "client")]
+
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use nextdoor::{
+    extract::Connection, ClientHandle, ConnectError, Disconnect, KeepaliveConfig, NextDoor,
+    ReconnectConfig, ReconnectPolicy,
+};
+use serde::Serialize;
+use tokio::{net::TcpListener, sync::Notify};
+use tokio_tungstenite::{
+    accept_async,
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+};
+
+async fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+#[tokio::test]
+async fn test_handle_sends_without_trigger() {
+    #[derive(Serialize)]
+    struct Subscribe {
+        op: &'static str,
+    }
+
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url);
+    let handle = client.handle();
+
+    handle.text("queued before connect").await.unwrap();
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("queued before connect".to_string())
+    );
+
+    handle.binary(vec![1, 2, 3]).await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Binary(vec![1, 2, 3])
+    );
+
+    handle.json(&Subscribe { op: "subscribe" }).await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text(r#"{"op":"subscribe"}"#.to_string())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_handle_survives_reconnect() {
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url);
+    let handle = client.handle();
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut first = accept_async(stream).await.unwrap();
+    first.close(None).await.unwrap();
+    drop(first);
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut second = accept_async(stream).await.unwrap();
+
+    handle.text("after reconnect").await.unwrap();
+    assert_eq!(
+        second.next().await.unwrap().unwrap(),
+        Message::Text("after reconnect".to_string())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_handle_tracks_connection_and_capacity() {
+    async fn wait_for(handle: &ClientHandle, closed: bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.is_closed() != closed {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    let (listener, url) = listen().await;
+    let mut client = nextdoor::connect(NextDoor::new(), url);
+    let handle = client.handle();
+    client.set_capacity(1);
+    assert_eq!(handle.queue_metrics().capacity, 1);
+    assert!(handle.is_closed());
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut first = accept_async(stream).await.unwrap();
+    wait_for(&handle, false).await;
+    first.close(None).await.unwrap();
+    while first.next().await.is_some() {}
+    drop(first);
+    wait_for(&handle, true).await;
+
+    // Sent while reconnecting, so it waits for the next connection.
+    handle.text("queued").await.unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut second = accept_async(stream).await.unwrap();
+    assert_eq!(
+        second.next().await.unwrap().unwrap(),
+        Message::Text("queued".to_string())
+    );
+    wait_for(&handle, false).await;
+
+    task.abort();
+    let _ = task.await;
+    assert!(handle.is_closed());
+}
+
+#[tokio::test]
+async fn test_clone_gets_its_own_queue_and_shutdown() {
+    let client = nextdoor::connect(NextDoor::new(), "ws://127.0.0.1:1").with_capacity(3);
+    let clone = client.clone();
+
+    client.handle().text("original").await.unwrap();
+    assert_eq!(client.queue_metrics().depth, 1);
+    assert_eq!(clone.queue_metrics().depth, 0);
+    assert_eq!(clone.queue_metrics().capacity, 3);
+
+    client.shutdown_handle().shutdown();
+    assert!(!clone.shutdown_handle().is_shutdown());
+}
+
+#[tokio::test]
+async fn test_lifecycle_hooks() {
+    let (listener, url) = listen().await;
+    let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+
+    let on_connect = events_tx.clone();
+    let on_disconnect = events_tx.clone();
+    let on_reconnect = events_tx;
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .on_connect(move |response, handle| {
+            let events = on_connect.clone();
+            async move {
+                events
+                    .send(format!("connect {}", response.status()))
+                    .unwrap();
+                handle.text("subscribe").await.unwrap();
+            }
+        })
+        .on_disconnect(move |disconnect, _| {
+            let events = on_disconnect.clone();
+            async move {
+                let Disconnect::Closed(Some(frame)) = disconnect else {
+                    panic!("unexpected disconnect: {:?}", disconnect);
+                };
+                events
+                    .send(format!("disconnect {} {}", frame.code, frame.reason))
+                    .unwrap();
+            }
+        })
+        .on_reconnect(move |attempt, _| {
+            let events = on_reconnect.clone();
+            async move {
+                events
+                    .send(format!("reconnect {}", attempt.attempt))
+                    .unwrap();
+            }
+        });
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut first = accept_async(stream).await.unwrap();
+    assert_eq!(
+        first.next().await.unwrap().unwrap(),
+        Message::Text("subscribe".to_string())
+    );
+    first
+        .close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "restart".into(),
+        }))
+        .await
+        .unwrap();
+    while first.next().await.is_some() {}
+    drop(first);
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut second = accept_async(stream).await.unwrap();
+    assert_eq!(
+        second.next().await.unwrap().unwrap(),
+        Message::Text("subscribe".to_string())
+    );
+
+    let mut received = Vec::new();
+    for _ in 0..4 {
+        received.push(events.recv().await.unwrap());
+    }
+    assert_eq!(
+        received,
+        [
+            "connect 101 Switching Protocols",
+            "disconnect 1001 restart",
+            "reconnect 1",
+            "connect 101 Switching Protocols",
+        ]
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_run_with_shutdown_closes_connection() {
+    let (listener, url) = listen().await;
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let connected = Arc::new(Notify::new());
+
+    let notify = connected.clone();
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_close_frame(nextdoor::request::CloseFrame {
+            reason: "bye".to_string(),
+            code: 1001,
+        })
+        .on_connect(move |_, _| {
+            let notify = notify.clone();
+            async move { notify.notify_one() }
+        });
+    let handle = client.handle();
+    let task = tokio::spawn(client.run_with_shutdown(async {
+        stop_rx.await.ok();
+    }));
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    connected.notified().await;
+
+    handle.text("first").await.unwrap();
+    handle.text("second").await.unwrap();
+    stop_tx.send(()).unwrap();
+
+    let mut received = Vec::new();
+    while let Some(msg) = server.next().await {
+        received.push(msg.unwrap());
+    }
+    assert_eq!(
+        received,
+        [
+            Message::Text("first".to_string()),
+            Message::Text("second".to_string()),
+            Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "bye".into(),
+            })),
+        ]
+    );
+    drop(server);
+
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_handle_stops_reconnecting() {
+    let (listener, url) = listen().await;
+    drop(listener);
+
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_reconnect_config(ReconnectConfig::default().with_initial_delay(60_000));
+    let shutdown = client.shutdown_handle();
+    let task = tokio::spawn(client.run_with_shutdown(std::future::pending()));
+
+    shutdown.shutdown();
+    assert!(shutdown.is_shutdown());
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_times_out_without_peer_close() {
+    let (listener, url) = listen().await;
+
+    let client =
+        nextdoor::connect(NextDoor::new(), url).with_close_timeout(Duration::from_millis(100));
+    let shutdown = client.shutdown_handle();
+    let task = tokio::spawn(client.run_with_shutdown(std::future::pending()));
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let _server = accept_async(stream).await.unwrap();
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_keepalive_sends_message() {
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url).with_keepalive(KeepaliveConfig {
+        interval: Duration::from_millis(20),
+        message: Message::Text(r#"{"op":"ping"}"#.to_string()),
+        timeout: None,
+    });
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Text(r#"{"op":"ping"}"#.to_string())
+        );
+    }
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_keepalive_timeout_reconnects() {
+    let (listener, url) = listen().await;
+    let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_keepalive(KeepaliveConfig {
+            interval: Duration::from_millis(20),
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        })
+        .on_disconnect(move |disconnect, _| {
+            let events = events_tx.clone();
+            async move {
+                events
+                    .send(matches!(disconnect, Disconnect::Timeout))
+                    .unwrap();
+            }
+        });
+    let task = tokio::spawn(client.run());
+
+    // Never read from the first connection, so pings are not answered.
+    let (stream, _) = listener.accept().await.unwrap();
+    let _silent = accept_async(stream).await.unwrap();
+
+    let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let _second = accept_async(stream).await.unwrap();
+    assert!(events.recv().await.unwrap());
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_keepalive_pong_keeps_connection() {
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url).with_keepalive(KeepaliveConfig {
+        interval: Duration::from_millis(20),
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+
+    // Reading answers each ping with a pong; the connection outlives several timeouts.
+    let reading = async { while server.next().await.is_some() {} };
+    let reconnected = listener.accept();
+    tokio::select! {
+        _ = reading => panic!("client closed the connection"),
+        _ = reconnected => panic!("client reconnected"),
+        _ = tokio::time::sleep(Duration::from_millis(400)) => {}
+    }
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_slow_handler_does_not_time_out() {
+    let (listener, url) = listen().await;
+    let mut router = NextDoor::new();
+    router.text(|text: String| async move {
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        text
+    });
+    let client = nextdoor::connect(router, url).with_keepalive(KeepaliveConfig {
+        interval: Duration::from_millis(20),
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    server
+        .send(Message::Text("slow".to_string()))
+        .await
+        .unwrap();
+
+    // Pongs to the keepalive pings arrive while the handler runs.
+    let reply = async {
+        loop {
+            match server.next().await.unwrap().unwrap() {
+                Message::Text(text) => return text,
+                Message::Close(_) => panic!("client closed the connection"),
+                _ => {}
+            }
+        }
+    };
+    tokio::select! {
+        text = reply => assert_eq!(text, "slow"),
+        _ = listener.accept() => panic!("client reconnected"),
+    }
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_reconnect_policy_applies_to_dropped_sessions() {
+    struct Attempts(tokio::sync::mpsc::UnboundedSender<u32>);
+
+    impl ReconnectPolicy for Attempts {
+        fn next_delay(&self, attempt: u32) -> Option<Duration> {
+            self.0.send(attempt).unwrap();
+            (attempt < 3).then_some(Duration::from_millis(10))
+        }
+
+        fn stable_after(&self) -> Duration {
+            Duration::from_secs(60)
+        }
+    }
+
+    let (listener, url) = listen().await;
+    let (attempts_tx, mut attempts) = tokio::sync::mpsc::unbounded_channel();
+    let client =
+        nextdoor::connect(NextDoor::new(), url).with_reconnect_policy(Attempts(attempts_tx));
+    let task = tokio::spawn(client.run());
+
+    // Each session drops right away, so the attempt count keeps growing until the policy gives up.
+    for _ in 0..3 {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = accept_async(stream).await.unwrap();
+        server.close(None).await.unwrap();
+        while server.next().await.is_some() {}
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(result, Err(ConnectError::MaxRetriesExceeded)));
+
+    let mut received = Vec::new();
+    while let Ok(attempt) = attempts.try_recv() {
+        received.push(attempt);
+    }
+    assert_eq!(received, [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_dropped_session_backs_off_without_policy() {
+    let (listener, url) = listen().await;
+    let task = tokio::spawn(nextdoor::connect(NextDoor::new(), url).run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    server.close(None).await.unwrap();
+    while server.next().await.is_some() {}
+    drop(server);
+    let dropped_at = tokio::time::Instant::now();
+
+    // The default backoff waits about a second before the first reconnect.
+    tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(dropped_at.elapsed() >= Duration::from_millis(700));
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_failed_reconnects_retry_without_policy() {
+    let (listener, url) = listen().await;
+    let addr = listener.local_addr().unwrap();
+    let task = tokio::spawn(nextdoor::connect(NextDoor::new(), url).run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    // The server goes away, so reconnects are refused for a while.
+    drop(listener);
+    server.close(None).await.unwrap();
+    while server.next().await.is_some() {}
+    drop(server);
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!task.is_finished());
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_reconnect_config_gives_up_on_failed_connects() {
+    let (listener, url) = listen().await;
+    drop(listener);
+
+    let client = nextdoor::connect(NextDoor::new(), url).with_reconnect_config(
+        ReconnectConfig::default()
+            .with_initial_delay(10)
+            .with_max_retries(2),
+    );
+
+    let result = tokio::time::timeout(Duration::from_secs(5), client.run())
+        .await
+        .unwrap();
+    assert!(matches!(result, Err(ConnectError::MaxRetriesExceeded)));
+}
+
+#[tokio::test]
+async fn test_handler_extracts_connection_and_handle() {
+    let (listener, url) = listen().await;
+    let mut router = NextDoor::new();
+    router.text(
+        |Connection(info): Connection, handle: ClientHandle, text: String| async move {
+            handle.text(format!("{} {}", info.url, text)).await.unwrap();
+        },
+    );
+    let task = tokio::spawn(nextdoor::connect(router, url.clone()).run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    server
+        .send(Message::Text("hello".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text(format!("{} hello", url))
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_connect_options() {
+    use nextdoor::ConnectOptions;
+    use tokio_tungstenite::{
+        accept_hdr_async,
+        tungstenite::handshake::server::{Request, Response},
+    };
+
+    let (listener, url) = listen().await;
+    let mut router = NextDoor::new();
+    router.text(
+        |Connection(info): Connection| async move { info.protocol.clone().unwrap_or_default() },
+    );
+    let client = nextdoor::connect(router, url).with_connect_options(
+        ConnectOptions::new()
+            .bearer_token("secret")
+            .unwrap()
+            .header("x-client", "nextdoor")
+            .unwrap()
+            .protocols(["feed.v2", "feed.v1"])
+            .max_message_size(Some(1024)),
+    );
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    #[allow(clippy::result_large_err)]
+    let callback = |req: &Request, mut res: Response| {
+        let headers = req.headers();
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(headers["x-client"], "nextdoor");
+        assert_eq!(headers["sec-websocket-protocol"], "feed.v2,feed.v1");
+        res.headers_mut()
+            .insert("sec-websocket-protocol", "feed.v1".parse().unwrap());
+        Ok(res)
+    };
+    let mut server = accept_hdr_async(stream, callback).await.unwrap();
+
+    let options = ConnectOptions::new().bearer_token("secret").unwrap();
+    assert!(!format!("{:?}", options).contains("secret"));
+    assert!(ConnectOptions::new().header("x-client", "a\nb").is_err());
+
+    server
+        .send(Message::Text("protocol".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("feed.v1".to_string())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_connect_target_runs_before_each_attempt() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nextdoor::ConnectTarget;
+    use tokio_tungstenite::{
+        accept_hdr_async,
+        tungstenite::handshake::server::{Request, Response},
+    };
+
+    let (listener, url) = listen().await;
+    let router = NextDoor::with_state(Arc::new(AtomicUsize::new(0)));
+    let client = nextdoor::connect(router, url).with_connect_target(
+        |url, attempts: Arc<AtomicUsize>| async move {
+            let token = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let target = ConnectTarget::new(format!("{}/feed?token={}", url, token))
+                .header("authorization", format!("Bearer {}", token))?
+                .header("x-attempt", "first")?
+                .header("x-attempt", "second")?;
+            Ok(target)
+        },
+    );
+    let task = tokio::spawn(client.run());
+
+    for token in 1..=2 {
+        let (stream, _) = listener.accept().await.unwrap();
+        #[allow(clippy::result_large_err)]
+        let callback = |req: &Request, res: Response| {
+            assert_eq!(req.uri(), format!("/feed?token={}", token).as_str());
+            assert_eq!(req.headers()["authorization"], format!("Bearer {}", token));
+            let attempts: Vec<_> = req.headers().get_all("x-attempt").iter().collect();
+            assert_eq!(attempts, ["first", "second"]);
+            Ok(res)
+        };
+        let mut server = accept_hdr_async(stream, callback).await.unwrap();
+        server.close(None).await.unwrap();
+        while server.next().await.is_some() {}
+    }
+
+    task.abort();
+}
+
+/// A CONNECT proxy that accepts one tunnel and reports the request head.
+async fn http_proxy(bind: &str) -> (String, tokio::sync::oneshot::Receiver<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = TcpListener::bind(bind).await.unwrap();
+    let url = format!("http://user:secret@{}", listener.local_addr().unwrap());
+    let (head_tx, head_rx) = tokio::sync::oneshot::channel();
+
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        let target = head.split_whitespace().nth(1).unwrap().to_string();
+        head_tx.send(head).unwrap();
+
+        let mut upstream = tokio::net::TcpStream::connect(target).await.unwrap();
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    });
+
+    (url, head_rx)
+}
+
+#[tokio::test]
+async fn test_http_connect_proxy() {
+    use nextdoor::Proxy;
+
+    let (listener, url) = listen().await;
+    let (proxy_url, head) = http_proxy("127.0.0.1:0").await;
+    let client =
+        nextdoor::connect(NextDoor::new(), url.clone()).with_proxy(Proxy::new(&proxy_url).unwrap());
+    let handle = client.handle();
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+
+    let head = head.await.unwrap();
+    let authority = url.trim_start_matches("ws://");
+    assert!(head.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", authority)));
+    // base64 of "user:secret"
+    assert!(head.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+
+    handle.text("through the tunnel").await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("through the tunnel".to_string())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_ipv6_proxy() {
+    use nextdoor::Proxy;
+
+    let (listener, url) = listen().await;
+    let (proxy_url, head) = http_proxy("[::1]:0").await;
+    assert!(proxy_url.contains("@[::1]:"));
+    let client =
+        nextdoor::connect(NextDoor::new(), url).with_proxy(Proxy::new(&proxy_url).unwrap());
+    let handle = client.handle();
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    head.await.unwrap();
+    handle.text("over ipv6").await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("over ipv6".to_string())
+    );
+
+    task.abort();
+}
+
+#[test]
+fn test_env_proxy_without_scheme() {
+    std::env::set_var("HTTP_PROXY", "proxy.corp:3128");
+    let proxy = nextdoor::Proxy::from_env("ws").unwrap();
+    std::env::remove_var("HTTP_PROXY");
+
+    let debug = format!("{:?}", proxy);
+    assert!(
+        debug.contains(r#"kind: Http, host: "proxy.corp", port: 3128"#),
+        "{}",
+        debug
+    );
+}
+
+/// A SOCKS5 proxy that accepts one tunnel to `upstream` and reports the address type requested.
+async fn socks5_proxy(upstream: String) -> (String, tokio::sync::oneshot::Receiver<u8>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let authority = listener.local_addr().unwrap().to_string();
+    let (atyp_tx, atyp_rx) = tokio::sync::oneshot::channel();
+
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut greeting = [0; 4];
+        client.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [5, 2, 0, 2]);
+        client.write_all(&[5, 2]).await.unwrap();
+
+        let mut auth = [0; 13];
+        client.read_exact(&mut auth).await.unwrap();
+        assert_eq!(&auth, b"\x01\x04user\x06secret");
+        client.write_all(&[1, 0]).await.unwrap();
+
+        let mut request = [0; 4];
+        client.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..3], [5, 1, 0]);
+        let len = match request[3] {
+            1 => 4,
+            4 => 16,
+            _ => client.read_u8().await.unwrap() as usize,
+        };
+        let mut address = vec![0; len + 2];
+        client.read_exact(&mut address).await.unwrap();
+        atyp_tx.send(request[3]).unwrap();
+
+        let mut upstream = tokio::net::TcpStream::connect(upstream).await.unwrap();
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    });
+
+    (authority, atyp_rx)
+}
+
+#[tokio::test]
+async fn test_socks5_proxy() {
+    use nextdoor::Proxy;
+
+    for (scheme, remote_dns) in [("socks5", false), ("socks5h", true)] {
+        let (listener, url) = listen().await;
+        let addr = listener.local_addr().unwrap();
+        let (authority, atyp) = socks5_proxy(addr.to_string()).await;
+        // "s%65cret" decodes to "secret".
+        let proxy = Proxy::new(&format!("{}://user:s%65cret@{}", scheme, authority)).unwrap();
+        let url = url.replace("127.0.0.1", "localhost");
+        let client = nextdoor::connect(NextDoor::new(), url).with_proxy(proxy);
+        let handle = client.handle();
+        let task = tokio::spawn(client.run());
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = accept_async(stream).await.unwrap();
+        // Only socks5h hands the host name to the proxy.
+        assert_eq!(atyp.await.unwrap() == 3, remote_dns);
+        handle.text("over socks").await.unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Text("over socks".to_string())
+        );
+
+        task.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_socks5_rejects_long_credentials() {
+    use nextdoor::Proxy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("socks5://{}", listener.local_addr().unwrap());
+    let received = tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut greeting = [0; 4];
+        client.read_exact(&mut greeting).await.unwrap();
+        client.write_all(&[5, 2]).await.unwrap();
+
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        rest
+    });
+
+    let proxy = Proxy::new(&proxy_url)
+        .unwrap()
+        .basic_auth("user", "s".repeat(256));
+    let result = nextdoor::connect(NextDoor::new(), "ws://127.0.0.1:1")
+        .with_proxy(proxy)
+        .with_reconnect_config(ReconnectConfig::default().with_max_retries(0))
+        .run()
+        .await;
+    assert!(matches!(result, Err(ConnectError::MaxRetriesExceeded)));
+    // The client gave up instead of sending a truncated length.
+    assert!(received.await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_no_proxy_connects_directly() {
+    use nextdoor::Proxy;
+
+    let (listener, url) = listen().await;
+    let port = url.rsplit(':').next().unwrap();
+    // Nothing listens on the proxy port, so only a direct connection succeeds.
+    let proxy = Proxy::new("http://127.0.0.1:1")
+        .unwrap()
+        .no_proxy(["localhost:1", format!("127.0.0.1:{}", port).as_str()]);
+    let task = tokio::spawn(
+        nextdoor::connect(NextDoor::new(), url)
+            .with_proxy(proxy)
+            .run(),
+    );
+
+    let (stream, _) = listener.accept().await.unwrap();
+    accept_async(stream).await.unwrap();
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_concurrent_execution() {
+    use nextdoor::{extract::State, ExecutionMode};
+
+    let (listener, url) = listen().await;
+    let release = Arc::new(Notify::new());
+    let mut router = NextDoor::with_state(release.clone());
+    router.text(
+        |State(release): State<Arc<Notify>>, text: String| async move {
+            if text == "slow" {
+                release.notified().await;
+            }
+            text
+        },
+    );
+    let client = nextdoor::connect(router, url)
+        .with_execution_mode(ExecutionMode::Concurrent { max_in_flight: 8 });
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    for text in ["slow", "fast"] {
+        server.send(Message::Text(text.to_string())).await.unwrap();
+    }
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("fast".to_string())
+    );
+
+    release.notify_one();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("slow".to_string())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_keyed_execution_keeps_order_per_key() {
+    use nextdoor::{
+        extract::{Json, State},
+        ExecutionMode,
+    };
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Tick {
+        channel: String,
+        n: u32,
+    }
+
+    let (listener, url) = listen().await;
+    let release = Arc::new(Notify::new());
+    let mut router = NextDoor::with_state(release.clone());
+    router.text(
+        |State(release): State<Arc<Notify>>, Json(tick): Json<Tick>| async move {
+            if tick.channel == "a" && tick.n == 1 {
+                release.notified().await;
+            }
+            format!("{}{}", tick.channel, tick.n)
+        },
+    );
+    let client =
+        nextdoor::connect(router, url).with_execution_mode(ExecutionMode::json_field(8, "channel"));
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    for (channel, n) in [("a", 1), ("a", 2), ("b", 1)] {
+        let tick = format!(r#"{{"channel":"{}","n":{}}}"#, channel, n);
+        server.send(Message::Text(tick)).await.unwrap();
+    }
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::Text("b1".to_string())
+    );
+
+    release.notify_one();
+    for expected in ["a1", "a2"] {
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Text(expected.to_string())
+        );
+    }
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_control_frames_jump_the_queue() {
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url);
+    let handle = client.handle();
+
+    handle.text("first").await.unwrap();
+    handle.text("second").await.unwrap();
+    handle.send(Message::Close(None)).await.unwrap();
+    handle.send(Message::Pong(b"pong".to_vec())).await.unwrap();
+    handle
+        .send_priority(Message::Text("urgent".to_string()))
+        .unwrap();
+    let metrics = handle.queue_metrics();
+    assert_eq!((metrics.depth, metrics.priority_depth), (5, 2));
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    let expected = [
+        Message::Pong(b"pong".to_vec()),
+        Message::Text("urgent".to_string()),
+        Message::Text("first".to_string()),
+        Message::Text("second".to_string()),
+        Message::Close(None),
+    ];
+    for message in expected {
+        assert_eq!(server.next().await.unwrap().unwrap(), message);
+    }
+    assert_eq!(handle.queue_metrics().sent, 5);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_priority_lane_is_bounded() {
+    use nextdoor::SendError;
+
+    let client = nextdoor::connect(NextDoor::new(), "ws://127.0.0.1:1");
+    let handle = client.handle();
+
+    let mut sent = 0;
+    let err = loop {
+        match handle.send_priority(Message::Text(sent.to_string())) {
+            Ok(()) => sent += 1,
+            Err(err) => break err,
+        }
+    };
+    assert!(matches!(err, SendError::Full));
+    assert_eq!(handle.queue_metrics().priority_depth, sent);
+    assert!(matches!(
+        handle.try_send(Message::Ping(Vec::new())),
+        Err(SendError::Full)
+    ));
+    handle
+        .try_send(Message::Text("normal".to_string()))
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_overflow_drop_policies() {
+    use nextdoor::{OverflowPolicy, SendError};
+
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_overflow_policy(OverflowPolicy::DropOldest)
+        .with_capacity(2);
+    let handle = client.handle();
+
+    for text in ["a", "b", "c"] {
+        handle.try_send(Message::Text(text.to_string())).unwrap();
+    }
+    let metrics = handle.queue_metrics();
+    assert_eq!((metrics.depth, metrics.dropped), (2, 1));
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    for text in ["b", "c"] {
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Text(text.to_string())
+        );
+    }
+    task.abort();
+
+    let client = nextdoor::connect(NextDoor::new(), "ws://127.0.0.1:1")
+        .with_overflow_policy(OverflowPolicy::DropNewest)
+        .with_capacity(1);
+    let handle = client.handle();
+    handle.text("kept").await.unwrap();
+    handle.text("dropped").await.unwrap();
+    assert_eq!(handle.queue_metrics().dropped, 1);
+
+    let client = nextdoor::connect(NextDoor::new(), "ws://127.0.0.1:1").with_capacity(1);
+    let handle = client.handle();
+    handle.try_send(Message::Text("kept".to_string())).unwrap();
+    assert!(matches!(
+        handle.try_send(Message::Text("full".to_string())),
+        Err(SendError::Full)
+    ));
+}
+
+#[tokio::test]
+async fn test_drop_oldest_keeps_close_frames() {
+    use nextdoor::OverflowPolicy;
+
+    let (listener, url) = listen().await;
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_overflow_policy(OverflowPolicy::DropOldest)
+        .with_capacity(1);
+    let handle = client.handle();
+
+    handle
+        .try_send(Message::Text("before".to_string()))
+        .unwrap();
+    handle.try_send(Message::Close(None)).unwrap();
+    handle.try_send(Message::Text("after".to_string())).unwrap();
+    assert_eq!(handle.queue_metrics().dropped, 1);
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Close(None));
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_overflow_disconnects() {
+    use nextdoor::OverflowPolicy;
+
+    let (listener, url) = listen().await;
+    let mut router = NextDoor::new();
+    router.text(|handle: ClientHandle| async move {
+        // Nothing is written while this runs on the single test thread.
+        for n in 0..10 {
+            handle.try_send(Message::Text(n.to_string())).unwrap();
+        }
+    });
+    let (disconnect_tx, mut disconnects) = tokio::sync::mpsc::unbounded_channel();
+    let client = nextdoor::connect(router, url)
+        .with_capacity(4)
+        .with_overflow_policy(OverflowPolicy::Disconnect)
+        .with_write_timeout(Duration::from_secs(5))
+        .on_disconnect(move |disconnect, _| {
+            let disconnect_tx = disconnect_tx.clone();
+            async move {
+                disconnect_tx.send(disconnect).unwrap();
+            }
+        });
+    let handle = client.handle();
+    let task = tokio::spawn(client.run());
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = accept_async(stream).await.unwrap();
+    server
+        .send(Message::Text("burst".to_string()))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        disconnects.recv().await.unwrap(),
+        Disconnect::Overflow
+    ));
+    assert_eq!(handle.queue_metrics().dropped, 6);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_write_timeout_when_peer_stops_reading() {
+    let (listener, url) = listen().await;
+    let (disconnect_tx, mut disconnects) = tokio::sync::mpsc::unbounded_channel();
+    let client = nextdoor::connect(NextDoor::new(), url)
+        .with_write_timeout(Duration::from_millis(200))
+        .on_disconnect(move |disconnect, _| {
+            let disconnect_tx = disconnect_tx.clone();
+            async move {
+                disconnect_tx.send(disconnect).unwrap();
+            }
+        });
+    let handle = client.handle();
+    let task = tokio::spawn(client.run());
+
+    // Accept the handshake, then never read, so the socket buffers fill up.
+    let (stream, _) = listener.accept().await.unwrap();
+    let _server = accept_async(stream).await.unwrap();
+    let writer =
+        tokio::spawn(async move { while handle.binary(vec![0; 1 << 20]).await.is_ok() {} });
+
+    let disconnect = tokio::time::timeout(Duration::from_secs(10), disconnects.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    match disconnect {
+        Disconnect::Error(tokio_tungstenite::tungstenite::Error::Io(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::TimedOut)
+        }
+        other => panic!("unexpected disconnect: {:?}", other),
+    }
+
+    writer.abort();
+    task.abort();
+}
@@ -0,0 +1,198 @@
+use bytes::Bytes;
+use nextdoor::{
+    codec::{Codec, JsonCodec},
+    extract::Typed,
+    request::{Frames, Request},
+    response::Status,
+    NextDoor,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Trade {
+    symbol: String,
+    price: u64,
+}
+
+fn trade() -> Trade {
+    Trade {
+        symbol: "BTC".to_string(),
+        price: 100,
+    }
+}
+
+async fn double(Typed(trade): Typed<Trade>) -> Typed<Trade> {
+    Typed(Trade {
+        price: trade.price * 2,
+        ..trade
+    })
+}
+
+#[tokio::test]
+async fn test_typed_defaults_to_json() {
+    let mut router = NextDoor::new();
+    router.text(double);
+
+    let body = JsonCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from(body)))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.frame, Frames::Text);
+    assert_eq!(response.body, r#"{"symbol":"BTC","price":200}"#);
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("{")))
+        .await;
+    assert_eq!(response.status, Status::DecodeError);
+    assert!(response.is_rejection());
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn test_msgpack() {
+    use nextdoor::{codec::MsgPackCodec, extract::MsgPack};
+
+    let mut router = NextDoor::new();
+    router.binary(|MsgPack(trade): MsgPack<Trade>| async move { MsgPack(trade.price) });
+
+    let body = MsgPackCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.frame, Frames::Binary);
+    assert_eq!(MsgPackCodec::decode::<u64>(&response.body).unwrap(), 100);
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn test_typed_uses_router_codec() {
+    use nextdoor::codec::{Format, MsgPackCodec};
+
+    let mut router = NextDoor::new();
+    router.binary(double).codec(Format::MsgPack);
+
+    let body = MsgPackCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.frame, Frames::Binary);
+    assert_eq!(
+        MsgPackCodec::decode::<Trade>(&response.body).unwrap(),
+        Trade {
+            symbol: "BTC".to_string(),
+            price: 200
+        }
+    );
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn test_merged_router_keeps_codec() {
+    use nextdoor::codec::{Format, MsgPackCodec};
+
+    let mut trades = NextDoor::new();
+    trades.binary(double).codec(Format::MsgPack);
+
+    let mut router = NextDoor::new();
+    router.text(double).merge(trades);
+
+    let body = MsgPackCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(
+        MsgPackCodec::decode::<Trade>(&response.body).unwrap().price,
+        200
+    );
+
+    let body = JsonCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from(body)))
+        .await;
+    assert_eq!(response.body, r#"{"symbol":"BTC","price":200}"#);
+}
+
+#[cfg(feature = "cbor")]
+#[tokio::test]
+async fn test_cbor() {
+    use nextdoor::{codec::CborCodec, extract::Cbor};
+
+    let mut router = NextDoor::new();
+    router.binary(|Cbor(trade): Cbor<Trade>| async move { Cbor(trade.symbol) });
+
+    let body = CborCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(CborCodec::decode::<String>(&response.body).unwrap(), "BTC");
+}
+
+#[cfg(feature = "bincode")]
+#[tokio::test]
+async fn test_bincode() {
+    use nextdoor::{codec::BincodeCodec, extract::Bincode};
+
+    let mut router = NextDoor::new();
+    router.binary(|Bincode(trade): Bincode<Trade>| async move { Bincode(trade) });
+
+    let body = BincodeCodec::encode(&trade()).unwrap();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(
+        BincodeCodec::decode::<Trade>(&response.body).unwrap(),
+        trade()
+    );
+
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(vec![1])))
+        .await;
+    assert_eq!(response.status, Status::DecodeError);
+}
+
+#[cfg(feature = "protobuf")]
+#[tokio::test]
+async fn test_protobuf() {
+    use nextdoor::extract::Protobuf;
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Quote {
+        #[prost(string, tag = "1")]
+        symbol: String,
+        #[prost(uint64, tag = "2")]
+        price: u64,
+    }
+
+    let mut router = NextDoor::new();
+    router.binary(|Protobuf(quote): Protobuf<Quote>| async move {
+        Protobuf(Quote {
+            price: quote.price + 1,
+            ..quote
+        })
+    });
+
+    let body = Quote {
+        symbol: "ETH".to_string(),
+        price: 41,
+    }
+    .encode_to_vec();
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.frame, Frames::Binary);
+    assert_eq!(
+        Quote::decode(response.body).unwrap(),
+        Quote {
+            symbol: "ETH".to_string(),
+            price: 42,
+        }
+    );
+
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from(vec![0xff])))
+        .await;
+    assert_eq!(response.status, Status::DecodeError);
+}
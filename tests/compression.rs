@@ -0,0 +1,139 @@
+#![cfg(any(feature = "gzip", feature = "zstd"))]
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use nextdoor::{
+    compression::{ContentEncoding, Encoding},
+    extract::{Decompressed, Json},
+    request::{Frames, Request},
+    response::Status,
+    NextDoor,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Trade {
+    symbol: String,
+    price: u64,
+}
+
+const TRADE: &str = r#"{"symbol":"BTC","price":100}"#;
+
+fn router<C>() -> NextDoor<Arc<()>>
+where
+    C: ContentEncoding + Send + Sync + 'static,
+{
+    let mut router = NextDoor::new();
+    router.binary(
+        |Decompressed(Json(trade), _): Decompressed<C, Json<Trade>>| async move { trade.symbol },
+    );
+    router
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn test_decompress_gzip_and_zlib() {
+    use nextdoor::compression::{Gzip, Zlib};
+
+    let routers = [
+        (Encoding::Gzip, router::<Gzip>()),
+        (Encoding::Zlib, router::<Zlib>()),
+    ];
+    for (encoding, router) in routers {
+        let body = encoding.compress(TRADE.as_bytes()).unwrap();
+        let response = router
+            .handler(Request::new(Frames::Binary, Bytes::from(body)))
+            .await;
+        assert_eq!(response.status, Status::OK);
+        assert_eq!(response.body, "BTC");
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[tokio::test]
+async fn test_decompress_zstd() {
+    let body = Encoding::Zstd.compress(TRADE.as_bytes()).unwrap();
+    let response = router::<nextdoor::compression::Zstd>()
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.body, "BTC");
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn test_uncompressed_falls_through() {
+    use nextdoor::compression::Zlib;
+
+    let mut router = NextDoor::new();
+    router
+        .binary(|Decompressed(body, _): Decompressed<Zlib, Bytes>| async move { body })
+        .binary(|body: Bytes| async move { body });
+
+    // Starts like a zlib header, but is not compressed.
+    for body in [&b"x^ not zlib"[..], &[0x78, 0x01, 0x08, 0x96, 0x01][..]] {
+        let response = router
+            .handler(Request::new(Frames::Binary, Bytes::copy_from_slice(body)))
+            .await;
+        assert_eq!(response.status, Status::OK);
+        assert_eq!(response.body, body);
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn test_corrupt_body_is_rejected() {
+    let mut body = Encoding::Gzip.compress(TRADE.as_bytes()).unwrap();
+    body.truncate(12);
+
+    let response = router::<nextdoor::compression::Gzip>()
+        .handler(Request::new(Frames::Binary, Bytes::from(body)))
+        .await;
+    assert_eq!(response.status, Status::DecodeError);
+    assert!(response.is_rejection());
+    assert!(response.source().is_some());
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn test_compressed_response() {
+    use nextdoor::compression::Compressed;
+
+    let mut router = NextDoor::new();
+    router.text(|| async { Compressed(Encoding::Gzip, TRADE) });
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("trade")))
+        .await;
+    assert_eq!(response.frame, Frames::Binary);
+    assert_eq!(
+        Encoding::Gzip.decompress(&response.body).unwrap(),
+        TRADE.as_bytes()
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[tokio::test]
+async fn test_compress_layer() {
+    use nextdoor::compression::compress;
+
+    let mut router = NextDoor::new();
+    router
+        .text(|| async { TRADE })
+        .binary(|| async { Status::NotImplemented })
+        .layer(compress(Encoding::Zstd));
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("trade")))
+        .await;
+    assert_eq!(response.frame, Frames::Binary);
+    assert_eq!(
+        Encoding::Zstd.decompress(&response.body).unwrap(),
+        TRADE.as_bytes()
+    );
+
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from("trade")))
+        .await;
+    assert_eq!(response.status, Status::NotImplemented);
+}
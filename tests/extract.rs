@@ -2,8 +2,8 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use nextdoor::{
-    error::ExtractError,
-    extract::{Binary, Close, FromMesasge, Json, State},
+    error::{ExtractError, JsonRejection},
+    extract::{Binary, Close, Extension, FromMesasge, Json, State},
     request::{Frames, Request},
 };
 use serde::{Deserialize, Serialize};
@@ -48,7 +48,19 @@ fn test_json_extractor_invalid_json() {
 
     let result = Json::<TestStruct>::call(&request, ());
     assert!(result.is_err());
-    assert!(matches!(result.unwrap_err(), ExtractError::JsonError(_)));
+    let rejection = result.unwrap_err();
+    assert_eq!(rejection.position(), Some((1, 24)));
+    assert!(!rejection.is_data());
+    #[cfg(not(feature = "simd-json"))]
+    assert!(matches!(
+        ExtractError::from(rejection),
+        ExtractError::JsonError(_)
+    ));
+    #[cfg(feature = "simd-json")]
+    assert!(matches!(
+        ExtractError::from(rejection),
+        ExtractError::SimdJsonError(_)
+    ));
 }
 
 #[test]
@@ -96,3 +108,35 @@ fn test_close_extractor() {
     assert_eq!(result.reason, "test reason".to_string());
     assert_eq!(result.code, 1000);
 }
+
+#[test]
+fn test_extension_extractor() {
+    let mut request = Request::new(Frames::Text, Bytes::from("{}"));
+
+    let result = Extension::<Arc<String>>::call(&request, ());
+    assert!(result.unwrap_err().0.contains("String"));
+
+    request
+        .extensions_mut()
+        .insert(Arc::new("envelope".to_string()));
+    let result = Extension::<Arc<String>>::call(&request, ());
+    assert_eq!(result.unwrap().0.as_str(), "envelope");
+}
+
+#[test]
+fn test_close_extractor_invalid_body() {
+    let request = Request::new(Frames::Close, Bytes::from(vec![0xff, 0xfe]));
+
+    let result = Close::call(&request, ());
+    assert!(matches!(result.unwrap_err(), JsonRejection::InvalidJson(_)));
+}
+
+#[test]
+fn test_bytes_extractor_shares_body() {
+    let body = Bytes::from(vec![1, 2, 3]);
+    let request = Request::new(Frames::Binary, body.clone());
+
+    let result = Bytes::call(&request, ()).unwrap();
+    assert_eq!(result, body);
+    assert_eq!(result.as_ptr(), body.as_ptr());
+}
@@ -4,7 +4,7 @@ use bytes::Bytes;
 
 use nextdoor::{
     error::ExtractError,
-    extract::{FromMesasge, State},
+    extract::{FromMesasge, FromRequestParts, State},
     handler::{ExtractorHandler, HandlerService},
     request::{Frames, Request},
     response::{IntoResponse, Response},
@@ -92,3 +92,85 @@ async fn test_handler_with_state() {
     let response = handler_service.call(request, state).await;
     assert_eq!(response.body, "State processed: 42");
 }
+
+#[tokio::test]
+async fn test_twelve_extractors_handler() {
+    #[allow(clippy::too_many_arguments)]
+    async fn handler(
+        State(state): State<Arc<i32>>,
+        a: MockExtractor,
+        b: MockExtractor,
+        c: MockExtractor,
+        d: MockExtractor,
+        e: MockExtractor,
+        f: MockExtractor,
+        g: MockExtractor,
+        h: MockExtractor,
+        i: MockExtractor,
+        j: MockExtractor,
+        req: Request,
+    ) -> MockResponse {
+        let args = [a, b, c, d, e, f, g, h, i, j].map(|arg| arg.0).concat();
+        MockResponse(format!("{} {} {}", state, args.len(), req.len()))
+    }
+
+    let request = Request::new(Frames::Text, Bytes::from("abc"));
+
+    let handler_service = ExtractorHandler {
+        handler,
+        _marker: PhantomData,
+    };
+
+    let response = handler_service.call(request, Arc::new(7)).await;
+    assert_eq!(response.body, "7 30 3");
+}
+
+#[tokio::test]
+async fn test_request_extractor_last() {
+    async fn handler(arg: MockExtractor, req: Request) -> MockResponse {
+        MockResponse(format!("{} {:?}", arg.0, req.path))
+    }
+
+    let request = Request::new(Frames::Text, Bytes::from("test_data"));
+
+    let handler_service = ExtractorHandler {
+        handler,
+        _marker: PhantomData,
+    };
+
+    let response = handler_service.call(request, ()).await;
+    assert_eq!(response.body, "test_data Text");
+}
+
+#[derive(Clone)]
+struct AsyncExtractor(usize);
+
+impl<S: Send> FromRequestParts<S> for AsyncExtractor {
+    type Rejection = ExtractError;
+
+    async fn call(req: &Request, _: S) -> Result<Self, Self::Rejection> {
+        tokio::task::yield_now().await;
+        Ok(AsyncExtractor(req.len()))
+    }
+}
+
+#[tokio::test]
+async fn test_async_extractor_handler() {
+    async fn handler(
+        len: AsyncExtractor,
+        arg: MockExtractor,
+        last: AsyncExtractor,
+    ) -> MockResponse {
+        MockResponse(format!("{} {} {}", len.0, arg.0, last.0))
+    }
+
+    let request = Request::new(Frames::Text, Bytes::from("test_data"));
+
+    let handler_service = ExtractorHandler {
+        handler,
+        _marker: PhantomData,
+    };
+
+    let response = handler_service.call(request, ()).await;
+    assert_eq!(response.body, "9 test_data 9");
+}
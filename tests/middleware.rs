@@ -0,0 +1,257 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bytes::Bytes;
+use nextdoor::{
+    extract::{Extension, Json},
+    handler::Handler,
+    middleware::{catch_panic, from_fn, Next},
+    request::{Frames, Request},
+    response::{IntoResponse, Response, Status},
+    NextDoor,
+};
+
+async fn prefix(req: Request, next: Next<Arc<()>>) -> Response {
+    let response = next.run(req).await;
+    let body = format!("layer({})", String::from_utf8_lossy(&response.body));
+    Response::new(response.status, body)
+}
+
+async fn reject(_: Request, _: Next<Arc<()>>) -> Response {
+    Response::error(Status::NotImplemented, "rejected")
+}
+
+#[tokio::test]
+async fn test_router_layer() {
+    let mut router = NextDoor::new();
+    router
+        .text(|req: String| async move { req })
+        .layer(from_fn(prefix))
+        .layer(from_fn(prefix));
+
+    let request = Request::new(Frames::Text, Bytes::from("hello"));
+    let response = router.handler(request).await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.body, "layer(layer(hello))");
+}
+
+#[tokio::test]
+async fn test_router_layer_skips_later_routes() {
+    let mut router = NextDoor::new();
+    router
+        .binary(|| async { "binary" })
+        .layer(from_fn(reject))
+        .text(|| async { "text" });
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("hello")))
+        .await;
+    assert_eq!(response.body, "text");
+
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from("hello")))
+        .await;
+    assert_eq!(response.status, Status::NotImplemented);
+    assert_eq!(response.body, "rejected");
+}
+
+#[tokio::test]
+async fn test_route_layer() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let count = from_fn(move |req: Request, next: Next<Arc<()>>| {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            next.run(req).await
+        }
+    });
+
+    let mut router = NextDoor::new();
+    router
+        .on("type", "counted", (|| async { "counted" }).layer(count))
+        .text(|| async { "plain" });
+
+    let response = router
+        .handler(Request::new(
+            Frames::Text,
+            Bytes::from(r#"{"type":"counted"}"#),
+        ))
+        .await;
+    assert_eq!(response.body, "counted");
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("plain")))
+        .await;
+    assert_eq!(response.body, "plain");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_catch_panic() {
+    let mut router = NextDoor::new();
+    router
+        .text(|req: String| async move {
+            if req == "boom" {
+                panic!("handler exploded");
+            }
+            req
+        })
+        .layer(catch_panic());
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("boom")))
+        .await;
+    assert_eq!(response.status, Status::InternalError);
+    assert_eq!(response.body, "handler exploded");
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("fine")))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.body, "fine");
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn test_tower_layer() {
+    use std::time::Duration;
+
+    use tower::timeout::TimeoutLayer;
+
+    let mut router = NextDoor::new();
+    router
+        .text(|req: String| async move {
+            if req == "slow" {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            req
+        })
+        .tower_layer(TimeoutLayer::new(Duration::from_millis(50)));
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("fast")))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.body, "fast");
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("slow")))
+        .await;
+    assert_eq!(response.status, Status::InternalError);
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn test_tower_layer_skips_later_routes() {
+    use std::time::Duration;
+
+    use tower::timeout::TimeoutLayer;
+
+    let slow = |body: Bytes| async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        body
+    };
+
+    let mut router = NextDoor::new();
+    router
+        .text(slow)
+        .tower_layer(TimeoutLayer::new(Duration::from_millis(50)))
+        .binary(slow);
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("wrapped")))
+        .await;
+    assert_eq!(response.status, Status::InternalError);
+
+    let response = router
+        .handler(Request::new(Frames::Binary, Bytes::from("added later")))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.body, "added later");
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn test_tower_layer_uses_nested_state() {
+    use nextdoor::extract::State;
+    use tower::layer::util::Identity;
+
+    let mut orders = NextDoor::with_state(0);
+    orders
+        .text(|State(state): State<i32>| async move { state.to_string() })
+        .tower_layer(Identity::new());
+
+    let mut router = NextDoor::with_state(7);
+    router.nest_with_state("channel", "orders", orders, |state: &i32| state * 2);
+
+    let response = router
+        .handler(Request::new(
+            Frames::Text,
+            Bytes::from(r#"{"channel":"orders"}"#),
+        ))
+        .await;
+    assert_eq!(response.body, "14");
+}
+
+#[tokio::test]
+async fn test_typed_responses_outside_handlers_are_encoded() {
+    use nextdoor::extract::Typed;
+
+    async fn short_circuit(_: Request, _: Next<Arc<()>>) -> Typed<u32> {
+        Typed(5)
+    }
+
+    let mut router = NextDoor::new();
+    router
+        .text(|| async { Status::InternalError })
+        .layer(from_fn(short_circuit))
+        .map_error(|response| Some(Typed(response.status.code()).into_response()));
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("hello")))
+        .await;
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(response.body, "5");
+
+    let reply = router
+        .error_reply(&Status::InternalError.into_response())
+        .unwrap();
+    assert_eq!(
+        reply.body,
+        Status::InternalError.code().to_string().as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn test_map_request_runs_once_per_message() {
+    #[derive(Clone)]
+    struct Envelope(usize);
+
+    let decoded = Arc::new(AtomicUsize::new(0));
+    let counter = decoded.clone();
+
+    let mut router = NextDoor::new();
+    router
+        .text(|Json(_): Json<serde_json::Value>| async { "json" })
+        .text(
+            |Extension(Envelope(len)): Extension<Envelope>,
+             Extension(tag): Extension<&'static str>| async move {
+                format!("{} {}", tag, len)
+            },
+        )
+        .layer(Extension("tagged"))
+        .map_request(move |req| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let envelope = Envelope(req.len());
+            req.extensions_mut().insert(envelope);
+        });
+
+    let response = router
+        .handler(Request::new(Frames::Text, Bytes::from("hello")))
+        .await;
+    assert_eq!(response.body, "tagged 5");
+    assert_eq!(decoded.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "client")]
+
+use std::time::Duration;
+
+use nextdoor::{ReconnectConfig, ReconnectPolicy};
+
+#[test]
+fn test_reconnect_config_backoff() {
+    let config = ReconnectConfig::default()
+        .with_initial_delay(100)
+        .with_max_delay(1000)
+        .with_max_retries(6)
+        .with_backoff_factor(2.0)
+        .with_jitter(0.0)
+        .with_reset_after(5000);
+
+    let delays: Vec<_> = (1..=6).map(|attempt| config.next_delay(attempt)).collect();
+    assert_eq!(
+        delays,
+        [100, 200, 400, 800, 1000, 1000].map(|ms| Some(Duration::from_millis(ms)))
+    );
+    assert_eq!(config.next_delay(7), None);
+    assert_eq!(config.stable_after(), Duration::from_secs(5));
+}
+
+#[test]
+fn test_reconnect_config_jitter() {
+    let config = ReconnectConfig::default()
+        .with_initial_delay(1000)
+        .with_jitter(0.5);
+
+    for _ in 0..100 {
+        let delay = config.next_delay(1).unwrap();
+        assert!(delay >= Duration::from_millis(500), "{:?}", delay);
+        assert!(delay <= Duration::from_millis(1500), "{:?}", delay);
+    }
+}
@@ -125,6 +125,20 @@ fn test_try_to_string() {
     assert!(request.try_to_string().is_err());
 }
 
+#[test]
+fn test_as_str_borrows_body() {
+    let body = Bytes::from("hello");
+    let request = Request::new(Frames::Text, body.clone());
+    let text = request.as_str().unwrap();
+    assert_eq!(text, "hello");
+    assert_eq!(text.as_ptr(), body.as_ptr());
+    assert_eq!(request.as_bytes().as_ptr(), body.as_ptr());
+    assert_eq!(request.into_body().as_ptr(), body.as_ptr());
+
+    let request = Request::new(Frames::Binary, Bytes::from(vec![0xFF, 0xFF]));
+    assert!(request.as_str().is_err());
+}
+
 #[test]
 fn test_to_vec() {
     let data = vec![1, 2, 3, 4];
@@ -142,3 +156,17 @@ fn test_frames_equality() {
     assert_eq!(Frames::Ping, Frames::Ping);
     assert_eq!(Frames::Pong, Frames::Pong);
 }
+
+#[test]
+fn test_clone_copies_extensions_on_write() {
+    let mut request = Request::new(Frames::Text, Bytes::from("hello"));
+    request.extensions_mut().insert(1u32);
+
+    let mut clone = request.clone();
+    clone.extensions_mut().insert(2u32);
+    clone.extensions_mut().insert("clone");
+
+    assert_eq!(request.extensions().get::<u32>(), Some(&1));
+    assert_eq!(request.extensions().get::<&str>(), None);
+    assert_eq!(clone.extensions().get::<u32>(), Some(&2));
+}
@@ -1,9 +1,16 @@
+use bytes::Bytes;
 use serde::Serialize;
 
 use nextdoor::{
-    extract::Json,
+    error::JsonRejection,
+    extract::{Binary, Close, FromMesasge, Json, Ping, Pong},
+    request::{CloseFrame, Frames, Request},
     response::{IntoResponse, Response, Status},
 };
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame as TCloseFrame},
+    Message,
+};
 
 macro_rules! into_response_success {
     (Result, $ok:ty, $success:expr, $status:ident) => {
@@ -49,11 +56,42 @@ fn test_status_methods() {
     assert!(Status::NotFound.is_error());
     assert!(Status::Reconnect.is_reconnect());
     assert!(Status::NotImplemented.is_error());
+    assert!(Status::InternalError.is_error());
     assert!(Status::JsonError.is_error());
-    assert!(Status::NotFountPath.is_error());
+    assert!(Status::NotFoundPath.is_error());
     assert!(Status::FromStringError.is_error());
 }
 
+#[test]
+fn test_status_codes() {
+    assert_eq!(Status::OK.code(), 200);
+    assert_eq!(Status::NotFoundPath.code(), 404);
+    assert_eq!(Status::Custom(4001).code(), 4001);
+    assert!(Status::Custom(4001).is_error());
+
+    #[allow(deprecated)]
+    let renamed = Status::NotFountPath;
+    assert_eq!(renamed, Status::NotFoundPath);
+}
+
+#[test]
+fn test_response_source_and_json_error() {
+    let rejection = Json::<Vec<u32>>::call(&Request::new(Frames::Text, Bytes::from("[1,")), ());
+    let response = rejection.unwrap_err().into_response();
+    assert_eq!(response.status, Status::JsonError);
+    assert!(response
+        .source()
+        .and_then(|source| source.downcast_ref::<JsonRejection>())
+        .is_some());
+
+    let reply = Response::error(Status::Custom(4001), "rate limited").to_json_error();
+    assert_eq!(reply.status, Status::OK);
+    assert_eq!(
+        reply.body,
+        r#"{"error":{"code":4001,"message":"rate limited","status":"Custom(4001)"}}"#
+    );
+}
+
 #[test]
 fn test_response_creation() {
     let response = Response::new(Status::OK, "test");
@@ -132,3 +170,58 @@ fn test_json_into_response() {
     assert_eq!(response.status, Status::OK);
     assert_eq!(response.body, r#"{"field":"test"}"#);
 }
+
+#[test]
+fn test_binary_into_response() {
+    let data = vec![1, 2, 3, 4];
+
+    for response in [
+        data.clone().into_response(),
+        Bytes::from(data.clone()).into_response(),
+        Binary(data.clone()).into_response(),
+    ] {
+        assert_eq!(response.status, Status::OK);
+        assert_eq!(response.frame, Frames::Binary);
+        assert_eq!(response.body, data);
+        assert_eq!(response.into_ws_message(), Message::Binary(data.clone()));
+    }
+
+    let response = Ping(data.clone()).into_response();
+    assert_eq!(response.frame, Frames::Ping);
+    assert_eq!(response.into_ws_message(), Message::Ping(data.clone()));
+
+    let response = Pong(data.clone()).into_response();
+    assert_eq!(response.frame, Frames::Pong);
+    assert_eq!(response.into_ws_message(), Message::Pong(data));
+}
+
+#[test]
+fn test_text_response_into_ws_message() {
+    let response = "hello".into_response();
+    assert_eq!(response.frame, Frames::Text);
+    assert_eq!(
+        response.into_ws_message(),
+        Message::Text("hello".to_string())
+    );
+}
+
+#[test]
+fn test_close_into_response() {
+    let response = Close(None).into_response();
+    assert_eq!(response.frame, Frames::Close);
+    assert_eq!(response.into_ws_message(), Message::Close(None));
+
+    let response = Close(Some(CloseFrame {
+        reason: "bye".to_string(),
+        code: 1000,
+    }))
+    .into_response();
+    assert_eq!(response.status, Status::OK);
+    assert_eq!(
+        response.into_ws_message(),
+        Message::Close(Some(TCloseFrame {
+            reason: "bye".into(),
+            code: CloseCode::Normal,
+        }))
+    );
+}
@@ -0,0 +1,186 @@
+#![cfg(feature = "server")]
+
+use futures_util::{SinkExt, StreamExt};
+use nextdoor::{
+    extract::{Binary, Connection, Json, Sequence},
+    response::{Response, Status},
+    NextDoor,
+};
+use tokio::net::TcpListener;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+};
+
+#[tokio::test]
+async fn test_serve_echo() {
+    let mut router = NextDoor::new();
+    router
+        .text(|req: String| async move { format!("echo: {}", req) })
+        .binary(|Binary(data): Binary| async move { data });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(nextdoor::serve(router, listener).run());
+
+    let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+
+    ws.send(Message::Text("hello".to_string())).await.unwrap();
+    let reply = ws.next().await.unwrap().unwrap();
+    assert_eq!(reply, Message::Text("echo: hello".to_string()));
+
+    ws.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    let reply = ws.next().await.unwrap().unwrap();
+    assert_eq!(reply, Message::Binary(vec![1, 2, 3]));
+
+    ws.close(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_serve_multiple_connections() {
+    let mut router = NextDoor::new();
+    router.text(|req: String| async move { req });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(nextdoor::serve(router, listener).run());
+
+    let (mut first, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+    let (mut second, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+
+    second
+        .send(Message::Text("second".to_string()))
+        .await
+        .unwrap();
+    first
+        .send(Message::Text("first".to_string()))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        first.next().await.unwrap().unwrap(),
+        Message::Text("first".to_string())
+    );
+    assert_eq!(
+        second.next().await.unwrap().unwrap(),
+        Message::Text("second".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_connection_context() {
+    let mut router = NextDoor::new();
+    router.text(
+        |Connection(info): Connection, Sequence(seq): Sequence| async move {
+            let token = info.headers["x-token"].to_str().unwrap().to_string();
+            format!("{} {} {} {}", info.id > 0, info.url, token, seq)
+        },
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(nextdoor::serve(router, listener).run());
+
+    let mut request = format!("ws://{}/feed?x=1", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("x-token", "secret".parse().unwrap());
+    let (mut ws, _) = connect_async(request).await.unwrap();
+
+    for seq in 1..=2 {
+        ws.send(Message::Text("hi".to_string())).await.unwrap();
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::Text(format!("true /feed?x=1 secret {}", seq))
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_map_error_replies_to_peer() {
+    let mut router = NextDoor::new();
+    router
+        .text(|Json(ids): Json<Vec<u32>>| async move {
+            if ids.is_empty() {
+                return Err(Response::error(Status::Custom(4001), "no ids"));
+            }
+            Ok(format!("{} ids", ids.len()))
+        })
+        .map_error(|response| Some(response.to_json_error()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(nextdoor::serve(router, listener).run());
+
+    let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+
+    ws.send(Message::Text("[]".to_string())).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Text(
+            r#"{"error":{"code":4001,"message":"no ids","status":"Custom(4001)"}}"#.to_string()
+        )
+    );
+
+    ws.send(Message::Text("[1,2]".to_string())).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Text("2 ids".to_string())
+    );
+
+    // The Json extractor rejects the message before the handler runs.
+    ws.send(Message::Text("not json".to_string()))
+        .await
+        .unwrap();
+    let reply = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+    assert_eq!(reply["error"]["status"], "JsonError");
+}
+
+#[tokio::test]
+async fn test_shutdown_closes_connections() {
+    use std::time::Duration;
+
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    let mut router = NextDoor::new();
+    router.text(|req: String| async move { req });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let mut server = nextdoor::serve(router, listener);
+    server.set_close_timeout(Duration::from_millis(300));
+    let server = tokio::spawn(server.run_with_shutdown(async {
+        shutdown_rx.await.ok();
+    }));
+
+    let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+    // A peer that never answers the close frame.
+    let (mut silent, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+    ws.send(Message::Text("hello".to_string())).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Text("hello".to_string())
+    );
+    silent
+        .send(Message::Text("ready".to_string()))
+        .await
+        .unwrap();
+    silent.next().await.unwrap().unwrap();
+
+    shutdown_tx.send(()).unwrap();
+    match ws.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+        message => panic!("expected a close frame, got {:?}", message),
+    }
+    assert!(ws.next().await.is_none());
+
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .unwrap()
+        .unwrap();
+    drop(silent);
+}